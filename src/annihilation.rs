@@ -0,0 +1,220 @@
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    boundary::Boundary,
+    grid::{Grid, Indexed},
+    particle::{
+        Dimensions, Dynamics, Momentum, Particle, ParticleBundle, SimulationClock, SpeedOfLight,
+    },
+    species::{SpeciesId, SpeciesRegistry},
+    SimulationRng,
+};
+
+/// Settings of the annihilation of particles with their antiparticles
+#[derive(Resource, Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Annihilation {
+    /// distance in m * k_e / e within which a pair annihilates, 0 disables annihilation
+    pub capture_distance: f32,
+    /// whether an annihilating pair emits two photons carrying its energy and momentum
+    pub photons: bool,
+}
+
+impl Default for Annihilation {
+    fn default() -> Self {
+        Self {
+            capture_distance: 1.0,
+            photons: true,
+        }
+    }
+}
+
+/// Annihilations since the start of the simulation
+#[derive(Resource, Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct AnnihilationStats {
+    /// number of annihilated pairs
+    pub pairs: u64,
+    /// energy released in electronvolts
+    pub energy: f64,
+    /// number of emitted photons
+    pub photons: u64,
+}
+
+impl AnnihilationStats {
+    /// Average number of annihilated pairs per simulated second
+    pub fn rate(&self, clock: &SimulationClock) -> f64 {
+        if clock.elapsed > 0.0 {
+            self.pairs as f64 / clock.elapsed
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Annihilates every particle with the closest of its antiparticles within the
+/// capture distance, in a fixed order so the result is deterministic
+#[allow(clippy::too_many_arguments)]
+pub fn annihilation_update(
+    mut commands: Commands,
+    query: Query<(Entity, &SpeciesId, &Particle, &Transform, &Momentum)>,
+    annihilation: Res<Annihilation>,
+    mut stats: ResMut<AnnihilationStats>,
+    registry: Res<SpeciesRegistry>,
+    dynamics: Res<Dynamics>,
+    c: Res<SpeedOfLight>,
+    boundary: Res<Boundary>,
+    dimensions: Res<Dimensions>,
+    mut rng: ResMut<SimulationRng>,
+) {
+    if annihilation.capture_distance <= 0.0 {
+        return;
+    }
+    let antiparticles = registry
+        .iter()
+        .map(|(id, _)| registry.antiparticle(id))
+        .collect::<Vec<_>>();
+
+    let particles = query
+        .iter()
+        .filter(|(_, species, ..)| antiparticles[species.0].is_some())
+        .collect::<Vec<_>>();
+    if particles.is_empty() {
+        return;
+    }
+    let grid = Grid::new(
+        particles
+            .iter()
+            .enumerate()
+            .map(|(index, (_, _, _, transform, _))| Indexed {
+                index,
+                translation: transform.translation,
+            }),
+        annihilation.capture_distance,
+        boundary.periodic_box(),
+    );
+    let photon = registry.find("photon").filter(|_| annihilation.photons);
+
+    let mut annihilated = vec![false; particles.len()];
+    for (i, &(entity, species, particle, transform, momentum)) in particles.iter().enumerate() {
+        if annihilated[i] {
+            continue;
+        }
+        let antiparticle = antiparticles[species.0];
+
+        // closest antiparticle, the first one on ties
+        let mut closest: Option<(f32, usize)> = None;
+        grid.for_each_neighbour(
+            transform.translation,
+            annihilation.capture_distance,
+            |diff, candidate| {
+                let j = candidate.index;
+                let distance = diff.length_squared();
+                if annihilated[j] || Some(*particles[j].1) != antiparticle {
+                    return;
+                }
+                if closest.is_none_or(|(d, k)| (distance, j) < (d, k)) {
+                    closest = Some((distance, j));
+                }
+            },
+        );
+        let Some((_, j)) = closest else {
+            continue;
+        };
+        annihilated[i] = true;
+        annihilated[j] = true;
+
+        let (other, _, other_particle, other_transform, other_momentum) = particles[j];
+        commands.entity(entity).despawn();
+        commands.entity(other).despawn();
+
+        let released = dynamics.energy(*c, particle.mass, momentum.0)
+            + dynamics.energy(*c, other_particle.mass, other_momentum.0);
+        stats.pairs += 1;
+        stats.energy += released as f64;
+
+        if let Some(photon) = photon {
+            // back to back in the centre of momentum frame, in a random direction
+            let direction = loop {
+                let mut direction =
+                    Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
+                if *dimensions == Dimensions::Three {
+                    direction.z = rng.gen_range(-1.0..1.0);
+                }
+                let length_squared = direction.length_squared();
+                if length_squared > 1e-6 && length_squared <= 1.0 {
+                    break direction.normalize();
+                }
+            };
+            let position = transform.translation
+                + boundary
+                    .periodic_box()
+                    .displacement(other_transform.translation - transform.translation)
+                    / 2.0;
+            let total = momentum.0 + other_momentum.0;
+            for momentum in photon_momenta(c.0, released, total, direction) {
+                commands.spawn(ParticleBundle::new(
+                    &registry,
+                    *dynamics,
+                    *c,
+                    photon,
+                    Transform::from_translation(position),
+                    momentum,
+                ));
+            }
+            stats.photons += 2;
+        }
+    }
+}
+
+/// Momenta of two photons that carry away the energy `released` and the momentum
+/// `total`, emitted back to back along `direction` in the centre of momentum
+/// frame of the pair and boosted into the lab frame
+fn photon_momenta(c: f32, released: f32, total: Vec3, direction: Vec3) -> [Vec3; 2] {
+    // energy of each photon in the centre of momentum frame, half the invariant mass
+    let half = (released * released - total.length_squared() * c * c)
+        .max(0.0)
+        .sqrt()
+        / 2.0;
+    if half <= f32::EPSILON * released {
+        return [total / 2.0; 2];
+    }
+    let gamma = released / (2.0 * half);
+    let axis = total.normalize_or_zero();
+    let rest = direction * (half / c);
+    // the part along the boost is stretched, and the photons gain half of the
+    // total momentum each from the motion of the frame
+    let k = rest + axis * ((gamma - 1.0) * rest.dot(axis));
+    [total / 2.0 + k, total / 2.0 - k]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn photons_conserve_energy_and_momentum() {
+        let c = 3.0;
+        for total in [
+            Vec3::ZERO,
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(-2.0, 5.0, 0.0),
+            Vec3::new(0.5, -0.25, 4.0),
+        ] {
+            for direction in [Vec3::X, Vec3::Y, Vec3::new(0.6, -0.8, 0.0), Vec3::Z] {
+                let released = 10.0 + total.length() * c;
+                let [a, b] = photon_momenta(c, released, total, direction);
+                assert!(
+                    (a + b - total).length() <= 1e-5,
+                    "{total} along {direction}"
+                );
+                let energy = (a.length() + b.length()) * c;
+                assert!(
+                    (energy - released).abs() <= 1e-4 * released,
+                    "{total} along {direction}: {energy} instead of {released}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::fmt;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    boundary::Boundary,
+    grid::{Grid, Indexed},
+    particle::Particle,
+};
+
+/// Settings of the detection of bound states
+#[derive(Resource, Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoundStateDetection {
+    /// distance in m * k_e / e within which two charged particles are considered
+    /// bound, 0 disables the detection
+    pub link_distance: f32,
+    /// whether to add a [`BoundState`] component to the particles of every bound state
+    pub tag: bool,
+}
+
+impl Default for BoundStateDetection {
+    fn default() -> Self {
+        Self {
+            link_distance: 3.0,
+            tag: true,
+        }
+    }
+}
+
+/// What a bound state is made of, judged from the charges of its particles
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum BoundKind {
+    /// uud
+    Proton,
+    /// udd
+    Neutron,
+    /// ūūd̄
+    Antiproton,
+    /// ūd̄d̄
+    Antineutron,
+    /// Any other three quarks or three antiquarks
+    Baryon,
+    /// A quark and an antiquark
+    Meson,
+    /// Quarks forming whole baryons, together with electrons
+    Atom,
+    /// Anything else
+    Other,
+}
+
+impl fmt::Display for BoundKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BoundKind::Proton => "proton",
+            BoundKind::Neutron => "neutron",
+            BoundKind::Antiproton => "antiproton",
+            BoundKind::Antineutron => "antineutron",
+            BoundKind::Baryon => "baryon",
+            BoundKind::Meson => "meson",
+            BoundKind::Atom => "atom",
+            BoundKind::Other => "other",
+        };
+        f.write_str(name)
+    }
+}
+
+impl BoundKind {
+    pub const ALL: [Self; 8] = [
+        BoundKind::Proton,
+        BoundKind::Neutron,
+        BoundKind::Antiproton,
+        BoundKind::Antineutron,
+        BoundKind::Baryon,
+        BoundKind::Meson,
+        BoundKind::Atom,
+        BoundKind::Other,
+    ];
+
+    /// Classifies a group of particles by their charges, in elementary charges
+    fn classify(charges: impl Iterator<Item = f32>) -> Self {
+        // number of up quarks, down quarks, up antiquarks, down antiquarks and electrons
+        let mut counts = [0; 5];
+        for charge in charges {
+            match (charge * 3.0).round() as i32 {
+                2 => counts[0] += 1,
+                -1 => counts[1] += 1,
+                -2 => counts[2] += 1,
+                1 => counts[3] += 1,
+                -3 => counts[4] += 1,
+                _ => return BoundKind::Other,
+            }
+        }
+
+        let [up, down, anti_up, anti_down, electrons] = counts;
+        let quarks = up + down;
+        let antiquarks = anti_up + anti_down;
+        match (quarks, antiquarks, electrons) {
+            (3, 0, 0) => match up {
+                2 => BoundKind::Proton,
+                1 => BoundKind::Neutron,
+                _ => BoundKind::Baryon,
+            },
+            (0, 3, 0) => match anti_up {
+                2 => BoundKind::Antiproton,
+                1 => BoundKind::Antineutron,
+                _ => BoundKind::Baryon,
+            },
+            (1, 1, 0) => BoundKind::Meson,
+            (q, 0, e) if q > 0 && q % 3 == 0 && e > 0 => BoundKind::Atom,
+            _ => BoundKind::Other,
+        }
+    }
+}
+
+/// Group of particles that are bound together
+#[derive(Clone, Debug)]
+pub struct Cluster {
+    pub kind: BoundKind,
+    pub members: Vec<Entity>,
+}
+
+/// Bound states found in the last fixed update
+#[derive(Resource, Clone, Debug, Default)]
+pub struct BoundStates {
+    pub clusters: Vec<Cluster>,
+}
+
+impl BoundStates {
+    /// Number of bound states of the given kind, and the number of particles in them
+    pub fn count(&self, kind: BoundKind) -> (usize, usize) {
+        self.clusters
+            .iter()
+            .filter(|cluster| cluster.kind == kind)
+            .fold((0, 0), |(states, particles), cluster| {
+                (states + 1, particles + cluster.members.len())
+            })
+    }
+}
+
+/// Marks a particle as part of a bound state
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BoundState {
+    pub kind: BoundKind,
+    /// index of the [`Cluster`] in [`BoundStates`]
+    pub cluster: usize,
+}
+
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Groups the charged particles within the link distance of each other using
+/// union-find, and classifies every group of more than one particle
+pub fn bound_state_update(
+    mut commands: Commands,
+    query: Query<(Entity, &Particle, &Transform, Option<&BoundState>)>,
+    detection: Res<BoundStateDetection>,
+    mut bound_states: ResMut<BoundStates>,
+    boundary: Res<Boundary>,
+) {
+    bound_states.clusters.clear();
+    if detection.link_distance <= 0.0 {
+        return;
+    }
+
+    let particles = query
+        .iter()
+        .filter(|(_, particle, ..)| particle.charge != 0.0)
+        .collect::<Vec<_>>();
+    let grid = Grid::new(
+        particles
+            .iter()
+            .enumerate()
+            .map(|(index, (_, _, transform, _))| Indexed {
+                index,
+                translation: transform.translation,
+            }),
+        detection.link_distance,
+        boundary.periodic_box(),
+    );
+
+    let mut parents = (0..particles.len()).collect::<Vec<_>>();
+    for (i, (_, _, transform, _)) in particles.iter().enumerate() {
+        grid.for_each_neighbour(
+            transform.translation,
+            detection.link_distance,
+            |_, other| {
+                let (a, b) = (find(&mut parents, i), find(&mut parents, other.index));
+                // the smallest index is the root, so the clusters come out in a fixed order
+                parents[a.max(b)] = a.min(b);
+            },
+        );
+    }
+
+    let mut clusters = vec![Vec::new(); particles.len()];
+    for i in 0..particles.len() {
+        clusters[find(&mut parents, i)].push(i);
+    }
+    let mut tags = vec![None; particles.len()];
+    for members in clusters.into_iter().filter(|members| members.len() > 1) {
+        let kind = BoundKind::classify(members.iter().map(|&i| particles[i].1.charge));
+        for &i in &members {
+            tags[i] = Some(BoundState {
+                kind,
+                cluster: bound_states.clusters.len(),
+            });
+        }
+        bound_states.clusters.push(Cluster {
+            kind,
+            members: members.iter().map(|&i| particles[i].0).collect(),
+        });
+    }
+
+    if !detection.tag {
+        return;
+    }
+    for (&(entity, _, _, old), tag) in particles.iter().zip(tags) {
+        match (old.copied(), tag) {
+            (old, Some(new)) if old != Some(new) => {
+                commands.entity(entity).insert(new);
+            }
+            (Some(_), None) => {
+                commands.entity(entity).remove::<BoundState>();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UP: f32 = 2.0 / 3.0;
+    const DOWN: f32 = -1.0 / 3.0;
+    const ELECTRON: f32 = -1.0;
+
+    fn classify(charges: &[f32]) -> BoundKind {
+        BoundKind::classify(charges.iter().copied())
+    }
+
+    #[test]
+    fn classifies_nucleons() {
+        assert_eq!(classify(&[UP, UP, DOWN]), BoundKind::Proton);
+        assert_eq!(classify(&[DOWN, UP, DOWN]), BoundKind::Neutron);
+        assert_eq!(classify(&[-UP, -UP, -DOWN]), BoundKind::Antiproton);
+        assert_eq!(classify(&[-UP, -DOWN, -DOWN]), BoundKind::Antineutron);
+    }
+
+    #[test]
+    fn classifies_other_hadrons() {
+        assert_eq!(classify(&[UP, UP, UP]), BoundKind::Baryon);
+        assert_eq!(classify(&[DOWN, DOWN, DOWN]), BoundKind::Baryon);
+        assert_eq!(classify(&[-DOWN, -DOWN, -DOWN]), BoundKind::Baryon);
+        assert_eq!(classify(&[UP, -UP]), BoundKind::Meson);
+        assert_eq!(classify(&[DOWN, -UP]), BoundKind::Meson);
+    }
+
+    #[test]
+    fn classifies_atoms() {
+        assert_eq!(classify(&[UP, UP, DOWN, ELECTRON]), BoundKind::Atom);
+        assert_eq!(
+            classify(&[UP, UP, DOWN, UP, DOWN, DOWN, ELECTRON, ELECTRON]),
+            BoundKind::Atom
+        );
+    }
+
+    #[test]
+    fn classifies_anything_else_as_other() {
+        assert_eq!(classify(&[ELECTRON, ELECTRON]), BoundKind::Other);
+        assert_eq!(classify(&[UP, DOWN]), BoundKind::Other);
+        assert_eq!(classify(&[UP, UP, -DOWN]), BoundKind::Other);
+        assert_eq!(classify(&[UP, -UP, ELECTRON]), BoundKind::Other);
+        // positrons and the charges of merged bodies are not quarks or electrons
+        assert_eq!(classify(&[UP, UP, DOWN, 1.0]), BoundKind::Other);
+        assert_eq!(classify(&[UP, UP, DOWN, 2.0]), BoundKind::Other);
+    }
+}
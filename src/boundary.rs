@@ -0,0 +1,94 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    force::PeriodicBox,
+    particle::{Momentum, StepForces, Velocity},
+    SIZE,
+};
+
+/// What happens to a particle leaving the box along an axis
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum BoundaryCondition {
+    /// The particle re-enters on the opposite side, and forces act across the boundary
+    #[default]
+    Periodic,
+    /// The particle bounces back off an elastic wall
+    Reflective,
+    /// The particle is despawned
+    Absorbing,
+    /// The particle continues unhindered, the box has no effect
+    Open,
+}
+
+/// Box the particles are simulated in, ranging from `-size` to `size`
+#[derive(Resource, Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct Boundary {
+    /// half of the side lengths of the box in m * k_e / e
+    pub size: Vec3,
+    /// condition along the x, y and z axis respectively
+    pub conditions: [BoundaryCondition; 3],
+}
+
+impl Default for Boundary {
+    fn default() -> Self {
+        Self {
+            size: SIZE,
+            conditions: [
+                BoundaryCondition::Periodic,
+                BoundaryCondition::Periodic,
+                BoundaryCondition::Open,
+            ],
+        }
+    }
+}
+
+impl Boundary {
+    /// Box with the periodic axes of the boundary, for the minimum image convention
+    pub fn periodic_box(&self) -> PeriodicBox {
+        let [x, y, z] = self.conditions.map(|c| c == BoundaryCondition::Periodic);
+        PeriodicBox {
+            min: -self.size,
+            size: self.size * 2.0,
+            periodic: BVec3::new(x, y, z),
+        }
+    }
+}
+
+pub fn boundary_update(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Transform, &mut Momentum, &mut Velocity)>,
+    boundary: Res<Boundary>,
+    mut forces: ResMut<StepForces>,
+) {
+    let max = boundary.size;
+    let min = -max;
+
+    for (entity, mut transform, mut momentum, mut velocity) in query.iter_mut() {
+        for (axis, condition) in boundary.conditions.into_iter().enumerate() {
+            let p = transform.translation[axis];
+            if (min[axis]..=max[axis]).contains(&p) {
+                continue;
+            }
+
+            match condition {
+                BoundaryCondition::Periodic => {
+                    let size = max[axis] - min[axis];
+                    transform.translation[axis] = min[axis] + (p - min[axis]).rem_euclid(size);
+                }
+                BoundaryCondition::Reflective => {
+                    let wall = if p > max[axis] { max[axis] } else { min[axis] };
+                    transform.translation[axis] = 2.0 * wall - p;
+                    momentum[axis] = -momentum[axis];
+                    velocity[axis] = -velocity[axis];
+                    forces.clear();
+                }
+                BoundaryCondition::Absorbing => {
+                    commands.entity(entity).despawn();
+                    break;
+                }
+                BoundaryCondition::Open => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,477 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use bevy::{prelude::*, utils::HashMap};
+use bincode::Options;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    annihilation::{Annihilation, AnnihilationStats},
+    bound::{bound_state_update, BoundState, BoundStateDetection},
+    boundary::Boundary,
+    collision::{CollisionStats, Collisions},
+    diagnostics::{Diagnostics, DriftThreshold},
+    field::{ExternalField, MagneticInteraction},
+    force::{ForceBackend, ForceLaw},
+    integrator::{Force, Integrator},
+    particle::{
+        Dimensions, Dynamics, Mass, Momentum, Particle, ParticleBundle, Radius, SimulationClock,
+        SpeedOfLight, StepForces, Timestep, Velocity,
+    },
+    scenario::{Scenario, ScenarioError},
+    species::{SpeciesId, SpeciesRegistry},
+    strong::{ColorCharge, StrongForce},
+    Seed, SimulationRng,
+};
+
+/// Identifies checkpoint files
+const MAGIC: &[u8; 8] = b"FYSIKSCP";
+/// Version of the checkpoint format, incremented whenever it changes
+const VERSION: u32 = 2;
+
+/// Encoding of the checkpoints, which reads at most `limit` bytes so a corrupt
+/// length cannot make loading allocate more than the file holds
+fn encoding(limit: u64) -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit(limit)
+}
+
+/// Settings of the checkpoints the simulation can be restarted from
+#[derive(Resource, Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Checkpointing {
+    /// file the checkpoints are written to, replacing the previous one, nothing
+    /// is written if not set
+    pub path: Option<PathBuf>,
+    /// number of integration steps between checkpoints
+    pub interval: u64,
+}
+
+impl Default for Checkpointing {
+    fn default() -> Self {
+        Self {
+            path: None,
+            interval: 1000,
+        }
+    }
+}
+
+/// Writes checkpoints every `interval` steps, at the end of a fixed update so
+/// restarting from them continues exactly like the original run in headless mode
+pub struct CheckpointPlugin;
+
+impl Plugin for CheckpointPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Checkpointing>()
+            .add_systems(Startup, restore_setup)
+            .add_systems(FixedUpdate, checkpoint_update.after(bound_state_update));
+    }
+}
+
+/// Configuration of the simulation
+#[derive(Serialize, Deserialize)]
+struct Config {
+    seed: u64,
+    boundary: Boundary,
+    timestep: Timestep,
+    integrator: Integrator,
+    dynamics: Dynamics,
+    speed_of_light: SpeedOfLight,
+    dimensions: Dimensions,
+    backend: ForceBackend,
+    law: ForceLaw,
+    strong: StrongForce,
+    field: ExternalField,
+    magnetic_interaction: MagneticInteraction,
+    annihilation: Annihilation,
+    collisions: Collisions,
+    bound_states: BoundStateDetection,
+    drift_threshold: DriftThreshold,
+    registry: SpeciesRegistry,
+}
+
+/// All components of a particle that are not derived from the others
+#[derive(Serialize, Deserialize)]
+struct ParticleState {
+    species: usize,
+    particle: Particle,
+    transform: Transform,
+    momentum: Vec3,
+    velocity: Vec3,
+    mass: f32,
+    radius: f32,
+    color: Option<ColorCharge>,
+    bound_state: Option<BoundState>,
+    /// force at the end of the last step, if the next step starts from it
+    force: Option<Force>,
+    /// level of the adaptive integrator the next step starts at
+    level: Option<u32>,
+}
+
+/// Complete state of a simulation
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    config: Config,
+    clock: SimulationClock,
+    rng: ChaCha8Rng,
+    annihilations: AnnihilationStats,
+    collisions: CollisionStats,
+    initial_energy: Option<f64>,
+    /// in the order the queries visit them, so the forces are summed in the same order
+    particles: Vec<ParticleState>,
+}
+
+/// Particles of the checkpoint a simulation is restarted from, spawned at startup
+#[derive(Resource)]
+struct RestoredParticles(Vec<ParticleState>);
+
+/// Step of the checkpoint a simulation is restarted from, so its output and
+/// trajectory are continued instead of replaced
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct Restarted(pub u64);
+
+impl Checkpoint {
+    fn capture(world: &mut World) -> Self {
+        let config = Config {
+            seed: world.resource::<Seed>().0,
+            boundary: *world.resource::<Boundary>(),
+            timestep: *world.resource::<Timestep>(),
+            integrator: *world.resource::<Integrator>(),
+            dynamics: *world.resource::<Dynamics>(),
+            speed_of_light: *world.resource::<SpeedOfLight>(),
+            dimensions: *world.resource::<Dimensions>(),
+            backend: *world.resource::<ForceBackend>(),
+            law: *world.resource::<ForceLaw>(),
+            strong: *world.resource::<StrongForce>(),
+            field: *world.resource::<ExternalField>(),
+            magnetic_interaction: *world.resource::<MagneticInteraction>(),
+            annihilation: *world.resource::<Annihilation>(),
+            collisions: *world.resource::<Collisions>(),
+            bound_states: *world.resource::<BoundStateDetection>(),
+            drift_threshold: *world.resource::<DriftThreshold>(),
+            registry: world.resource::<SpeciesRegistry>().clone(),
+        };
+        let step_forces = world.resource::<StepForces>().clone();
+        let indices = step_forces
+            .entities
+            .iter()
+            .enumerate()
+            .map(|(i, &entity)| (entity, i))
+            .collect::<HashMap<_, _>>();
+        let particles = world
+            .query::<(
+                Entity,
+                &SpeciesId,
+                &Particle,
+                &Transform,
+                &Momentum,
+                &Velocity,
+                &Mass,
+                &Radius,
+                Option<&ColorCharge>,
+                Option<&BoundState>,
+            )>()
+            .iter(world)
+            .map(
+                |(
+                    entity,
+                    species,
+                    particle,
+                    transform,
+                    momentum,
+                    velocity,
+                    mass,
+                    radius,
+                    color,
+                    bound,
+                )| {
+                    ParticleState {
+                        species: species.0,
+                        particle: *particle,
+                        transform: *transform,
+                        momentum: momentum.0,
+                        velocity: velocity.0,
+                        mass: mass.0,
+                        radius: radius.0,
+                        color: color.copied(),
+                        bound_state: bound.copied(),
+                        force: step_forces
+                            .forces
+                            .as_ref()
+                            .zip(indices.get(&entity))
+                            .map(|(forces, &i)| forces[i]),
+                        level: step_forces
+                            .levels
+                            .as_ref()
+                            .zip(indices.get(&entity))
+                            .map(|(levels, &i)| levels[i]),
+                    }
+                },
+            )
+            .collect();
+
+        Self {
+            config,
+            clock: *world.resource::<SimulationClock>(),
+            rng: world.resource::<SimulationRng>().0.clone(),
+            annihilations: *world.resource::<AnnihilationStats>(),
+            collisions: *world.resource::<CollisionStats>(),
+            initial_energy: world.resource::<Diagnostics>().initial_energy,
+            particles,
+        }
+    }
+
+    /// Writes the checkpoint to a temporary file first, so an interruption
+    /// never leaves a partial checkpoint behind
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let mut file = BufWriter::new(File::create(&temporary)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&VERSION.to_le_bytes())?;
+        encoding(u64::MAX)
+            .serialize_into(&mut file, self)
+            .map_err(io::Error::other)?;
+        file.into_inner()?.sync_all()?;
+        fs::rename(temporary, path)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ScenarioError> {
+        let error = |message: String| ScenarioError::Checkpoint(message);
+        let file =
+            File::open(path).map_err(|e| error(format!("could not read the checkpoint: {e}")))?;
+        let len = file
+            .metadata()
+            .map_err(|e| error(format!("could not read the checkpoint: {e}")))?
+            .len();
+        let mut file = BufReader::new(file);
+        let mut header = [0; 12];
+        file.read_exact(&mut header)
+            .map_err(|e| error(format!("could not read the checkpoint: {e}")))?;
+        if &header[..8] != MAGIC {
+            return Err(error("not a checkpoint".to_owned()));
+        }
+        let version = u32::from_le_bytes(header[8..].try_into().unwrap());
+        if version != VERSION {
+            return Err(error(format!(
+                "checkpoint version {version} is not supported, expected {VERSION}"
+            )));
+        }
+        encoding(len)
+            .deserialize_from(file)
+            .map_err(|e| error(format!("corrupt checkpoint: {e}")))
+    }
+
+    /// Replaces the configuration and state of `app` with the checkpoint
+    pub fn insert_resources(self, app: &mut App) {
+        let config = self.config;
+        app.insert_resource(Seed(config.seed))
+            .insert_resource(config.boundary)
+            .insert_resource(config.timestep)
+            .insert_resource(config.integrator)
+            .insert_resource(config.dynamics)
+            .insert_resource(config.speed_of_light)
+            .insert_resource(config.dimensions)
+            .insert_resource(config.backend)
+            .insert_resource(config.law)
+            .insert_resource(config.strong)
+            .insert_resource(config.field)
+            .insert_resource(config.magnetic_interaction)
+            .insert_resource(config.annihilation)
+            .insert_resource(config.collisions)
+            .insert_resource(config.bound_states)
+            .insert_resource(config.drift_threshold)
+            .insert_resource(config.registry)
+            .insert_resource(Restarted(self.clock.steps))
+            .insert_resource(self.clock)
+            .insert_resource(SimulationRng(self.rng))
+            .insert_resource(self.annihilations)
+            .insert_resource(self.collisions)
+            .insert_resource(Diagnostics::resumed(self.initial_energy))
+            .insert_resource(Scenario::default())
+            .insert_resource(RestoredParticles(self.particles));
+    }
+}
+
+/// Writes the state of the simulation to `path`, logging any errors
+pub fn save_checkpoint(world: &mut World, path: &Path) {
+    match Checkpoint::capture(world).save(path) {
+        Ok(()) => info!("saved checkpoint {}", path.display()),
+        Err(error) => error!("could not save checkpoint {}: {error}", path.display()),
+    }
+}
+
+/// Saves a checkpoint whenever the steps pass a multiple of the interval,
+/// where `saved` is the step of the last checkpoint, starting at the one a
+/// restarted simulation continues from so it is not overwritten right away
+fn checkpoint_update(world: &mut World, mut saved: Local<Option<u64>>) {
+    let checkpointing = world.resource::<Checkpointing>();
+    let Some(path) = checkpointing.path.clone() else {
+        return;
+    };
+    let steps = world.resource::<SimulationClock>().steps;
+    let interval = checkpointing.interval.max(1);
+    let restarted = world
+        .get_resource::<Restarted>()
+        .map_or(0, |restarted| restarted.0);
+    let saved = saved.get_or_insert(restarted);
+    if steps / interval > *saved / interval {
+        save_checkpoint(world, &path);
+        *saved = steps;
+    }
+}
+
+/// Spawns the particles of a checkpoint in their original order, adding the
+/// optional components in the same order as during the original run so every
+/// particle ends up in the same place of the same table, and the next step
+/// starts from the same forces and levels
+fn restore_setup(world: &mut World) {
+    let Some(RestoredParticles(restored)) = world.remove_resource::<RestoredParticles>() else {
+        return;
+    };
+    let registry = world.resource::<SpeciesRegistry>().clone();
+    let dynamics = *world.resource::<Dynamics>();
+    let c = *world.resource::<SpeedOfLight>();
+
+    let mut states = HashMap::new();
+    for state in restored {
+        let mut entity = world.spawn(ParticleBundle::new(
+            &registry,
+            dynamics,
+            c,
+            SpeciesId(state.species),
+            state.transform,
+            state.momentum,
+        ));
+        entity.insert((
+            state.particle,
+            Velocity(state.velocity),
+            Mass(state.mass),
+            Radius(state.radius),
+        ));
+        if let Some(color) = state.color {
+            entity.insert(color);
+        }
+        if let Some(bound_state) = state.bound_state {
+            entity.insert(bound_state);
+        }
+        states.insert(entity.id(), (state.force, state.level));
+    }
+
+    // moving particles between tables reorders them, so the forces follow the
+    // order the queries visit the particles in
+    let entities = world
+        .query_filtered::<Entity, With<Particle>>()
+        .iter(world)
+        .collect::<Vec<_>>();
+    world.insert_resource(StepForces {
+        forces: entities.iter().map(|entity| states[entity].0).collect(),
+        levels: entities.iter().map(|entity| states[entity].1).collect(),
+        entities,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::time::TimeUpdateStrategy;
+    use clap::Parser;
+
+    use super::*;
+    use crate::{cli::Cli, SimulationPlugin};
+
+    const STEPS: u64 = 40;
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fysiks-{}-{name}.bin", std::process::id()))
+    }
+
+    /// Headless simulation of a few particles in a small box, where they
+    /// interact, collide and reach the walls
+    fn app(restart: Option<&Path>) -> App {
+        let mut args = [
+            "fysiks",
+            "--size=20",
+            "--boundary=periodic,reflective,open",
+            "--seed=7",
+            "--electrons=12",
+            "--up-quarks=12",
+            "--down-quarks=6",
+            "--positrons=6",
+            "--collisions=bounce",
+            "--integrator=velocity-verlet",
+        ]
+        .map(String::from)
+        .to_vec();
+        if let Some(restart) = restart {
+            args.push(format!("--restart={}", restart.display()));
+        }
+
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, SimulationPlugin));
+        Cli::parse_from(args).insert_resources(&mut app).unwrap();
+        app.finish();
+        app.cleanup();
+        let duration = app.world.resource::<Timestep>().duration();
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(duration));
+        app
+    }
+
+    fn run(app: &mut App, steps: u64) {
+        while app.world.resource::<SimulationClock>().steps < steps {
+            app.update();
+        }
+    }
+
+    /// Encoded state of the simulation, equal for equal states
+    fn state(app: &mut App) -> Vec<u8> {
+        encoding(u64::MAX)
+            .serialize(&Checkpoint::capture(&mut app.world))
+            .unwrap()
+    }
+
+    #[test]
+    fn restarts_continue_like_the_original_run() {
+        let checkpoint = path("restart");
+        let mut original = app(None);
+        run(&mut original, STEPS / 2);
+        save_checkpoint(&mut original.world, &checkpoint);
+        run(&mut original, STEPS);
+
+        let mut restarted = app(Some(&checkpoint));
+        run(&mut restarted, STEPS);
+        fs::remove_file(checkpoint).unwrap();
+
+        assert_eq!(restarted.world.resource::<Restarted>().0, STEPS / 2);
+        assert!(original.world.resource::<CollisionStats>().bounces > 0);
+        assert!(state(&mut original) == state(&mut restarted));
+    }
+
+    #[test]
+    fn corrupt_lengths_are_errors() {
+        let checkpoint = path("corrupt");
+        let mut app = app(None);
+        run(&mut app, 1);
+        save_checkpoint(&mut app.world, &checkpoint);
+
+        // the length of the first species name, far more than the file holds
+        let mut bytes = fs::read(&checkpoint).unwrap();
+        let name = bytes
+            .windows(8)
+            .position(|window| window == b"electron")
+            .unwrap();
+        bytes[name - 8..name].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+        fs::write(&checkpoint, bytes).unwrap();
+        let result = Checkpoint::load(&checkpoint);
+        fs::remove_file(checkpoint).unwrap();
+
+        assert!(matches!(
+            result,
+            Err(ScenarioError::Checkpoint(message)) if message.starts_with("corrupt checkpoint")
+        ));
+    }
+}
@@ -0,0 +1,443 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use clap::{Parser, ValueEnum};
+
+use crate::{
+    annihilation::Annihilation,
+    bound::BoundStateDetection,
+    boundary::{Boundary, BoundaryCondition},
+    checkpoint::{Checkpoint, Checkpointing},
+    collision::{CollisionMode, Collisions},
+    diagnostics::DiagnosticsSettings,
+    field::{ExternalField, MagneticInteraction},
+    force::{ForceBackend, ForceLaw, Softening},
+    integrator::Integrator,
+    output::{Output, OutputFormat},
+    particle::{Dimensions, Dynamics, SpeedOfLight, Timestep},
+    scenario::{Scenario, ScenarioError},
+    strong::StrongForce,
+    trajectory::{read_frame, Trajectory},
+    Seed,
+};
+
+/// Particle simulation of electrons and quarks interacting through Coulomb's law
+#[derive(Parser, Debug)]
+#[command(version)]
+pub struct Cli {
+    /// RON file with the initial conditions, replaces the particle counts and
+    /// overrides the settings it contains
+    #[arg(long)]
+    pub scenario: Option<PathBuf>,
+    /// Extended XYZ file whose frame replaces the particles of the scenario
+    #[arg(long)]
+    pub initial_frame: Option<PathBuf>,
+    /// Index of the frame of `--initial-frame`, the last one if not given
+    #[arg(long, requires = "initial_frame")]
+    pub frame: Option<usize>,
+    /// Checkpoint to continue from, with its configuration instead of the one
+    /// given by the arguments. Existing output and trajectory files are continued
+    #[arg(long, conflicts_with_all = ["scenario", "initial_frame"])]
+    pub restart: Option<PathBuf>,
+    /// Number of electrons
+    #[arg(long, default_value_t = 1000)]
+    pub electrons: u32,
+    /// Number of up quarks
+    #[arg(long, default_value_t = 1000)]
+    pub up_quarks: u32,
+    /// Number of down quarks
+    #[arg(long, default_value_t = 1000)]
+    pub down_quarks: u32,
+    /// Number of positrons
+    #[arg(long, default_value_t = 0)]
+    pub positrons: u32,
+    /// Number of up antiquarks
+    #[arg(long, default_value_t = 0)]
+    pub anti_up_quarks: u32,
+    /// Number of down antiquarks
+    #[arg(long, default_value_t = 0)]
+    pub anti_down_quarks: u32,
+
+    /// Half of the side lengths of the box, as `x,y,z` or a single value for all axes
+    #[arg(long, value_parser = parse_vec3, default_value = "400")]
+    pub size: Vec3,
+    /// Boundary conditions along the x, y and z axis, as `x,y,z` or a single
+    /// value for all axes [possible values: periodic, reflective, absorbing, open]
+    #[arg(long, value_parser = parse_boundary, default_value = "periodic,periodic,open")]
+    pub boundary: [BoundaryCondition; 3],
+
+    /// Seed of the random number generator, random if not given
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Simulated seconds per fixed update
+    #[arg(long, default_value_t = 1.0 / 60.0)]
+    pub timestep: f32,
+    /// Number of integration steps per fixed update
+    #[arg(long, default_value_t = 1)]
+    pub substeps: u32,
+    /// Number of integration steps to run before exiting, runs forever if not given
+    #[arg(long, required_if_eq("headless", "true"))]
+    pub steps: Option<u64>,
+
+    /// Algorithm used to sum the forces between particles
+    #[arg(long, value_enum, default_value_t = BackendArg::Direct)]
+    pub backend: BackendArg,
+    /// Opening angle of the Barnes-Hut tree
+    #[arg(long, default_value_t = 0.5)]
+    pub theta: f32,
+    /// Cutoff radius of the cutoff backend
+    #[arg(long, default_value_t = 50.0)]
+    pub cutoff: f32,
+    /// Splitting parameter of Ewald summation
+    #[arg(long, default_value_t = 0.016)]
+    pub ewald_alpha: f32,
+    /// Cutoff radius of the real space part of Ewald summation
+    #[arg(long, default_value_t = 200.0)]
+    pub ewald_cutoff: f32,
+    /// Number of wave vectors along every periodic axis in Ewald summation
+    #[arg(long, default_value_t = 13)]
+    pub ewald_k_max: u32,
+
+    /// Kernel that softens Coulomb's law at short distances
+    #[arg(long, value_enum, default_value_t = SofteningArg::Plummer)]
+    pub softening: SofteningArg,
+    /// Softening length of the Plummer and spline kernels, or the radius of the
+    /// hard-core kernel, overridden by the softening of a species
+    #[arg(long, default_value_t = 1.0)]
+    pub softening_length: f32,
+
+    /// Distance within which a particle and its antiparticle annihilate, 0 disables annihilation
+    #[arg(long, default_value_t = 1.0)]
+    pub capture_distance: f32,
+    /// Annihilate without emitting photons
+    #[arg(long)]
+    pub no_photons: bool,
+
+    /// Coefficient of the Coulomb-like term of the strong force between quarks
+    #[arg(long, default_value_t = 2.0)]
+    pub strong_coupling: f32,
+    /// String tension of the linear confinement term of the strong force
+    #[arg(long, default_value_t = 0.05)]
+    pub string_tension: f32,
+    /// Range of the strong force, 0 disables it
+    #[arg(long, default_value_t = 20.0)]
+    pub strong_range: f32,
+
+    /// How overlapping particles are resolved
+    #[arg(long, value_enum, default_value_t = CollisionArg::None)]
+    pub collisions: CollisionArg,
+    /// Ratio of the relative speed after and before a bounce, 1 for elastic collisions
+    #[arg(long, default_value_t = 1.0)]
+    pub restitution: f32,
+
+    /// Uniform external electric field, as `x,y,z`
+    #[arg(long, value_parser = parse_vec3, default_value = "0")]
+    pub electric_field: Vec3,
+    /// Uniform external magnetic field, as `x,y,z`
+    #[arg(long, value_parser = parse_vec3, default_value = "0")]
+    pub magnetic_field: Vec3,
+    /// Let moving charges interact through their magnetic fields, O(N²)
+    #[arg(long)]
+    pub magnetic_interaction: bool,
+
+    /// Distance within which charged particles are considered bound, 0 disables
+    /// the detection of bound states
+    #[arg(long, default_value_t = 3.0)]
+    pub bound_distance: f32,
+
+    /// Relative energy drift above which a warning is logged
+    #[arg(long, default_value_t = 0.01)]
+    pub drift_threshold: f64,
+    /// Only compute the energies and momenta when the output takes a sample
+    #[arg(long)]
+    pub no_diagnostics: bool,
+    /// Number of integration steps between computations of the energies and momenta
+    #[arg(long, default_value_t = 100)]
+    pub diagnostics_interval: u64,
+
+    /// File the observables are written to every `--output-interval` steps
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+    /// Format of the observables, a directory with a binary file per column for columnar
+    #[arg(long, value_enum, default_value_t = OutputFormatArg::Csv)]
+    pub output_format: OutputFormatArg,
+    /// Number of integration steps between samples of the observables
+    #[arg(long, default_value_t = 1)]
+    pub output_interval: u64,
+
+    /// Extended XYZ file the trajectory is written to every `--trajectory-interval` steps
+    #[arg(long)]
+    pub trajectory: Option<PathBuf>,
+    /// Number of integration steps between frames of the trajectory
+    #[arg(long, default_value_t = 100)]
+    pub trajectory_interval: u64,
+
+    /// File a checkpoint is written to every `--checkpoint-interval` steps and
+    /// at the end of a headless run
+    #[arg(long)]
+    pub checkpoint: Option<PathBuf>,
+    /// Number of integration steps between checkpoints
+    #[arg(long, default_value_t = 1000)]
+    pub checkpoint_interval: u64,
+
+    /// Numerical scheme used to advance the particles
+    #[arg(long, value_enum, default_value_t = IntegratorArg::SemiImplicitEuler)]
+    pub integrator: IntegratorArg,
+    /// Deepest level of the adaptive integrator
+    #[arg(long, default_value_t = 6)]
+    pub max_level: u32,
+    /// Accuracy parameter of the adaptive integrator
+    #[arg(long, default_value_t = 0.02)]
+    pub eta: f32,
+
+    /// Use classical instead of relativistic mechanics, so particles can
+    /// exceed the speed of light
+    #[arg(long)]
+    pub newtonian: bool,
+    /// Speed of light, the speed limit of relativistic mechanics
+    #[arg(long, default_value_t = SpeedOfLight::default().0)]
+    pub speed_of_light: f32,
+
+    /// Simulate in 3D instead of in the xy-plane
+    #[arg(long = "3d")]
+    pub three_d: bool,
+    /// Run without a window, as fast as possible
+    #[arg(long)]
+    pub headless: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum BackendArg {
+    Direct,
+    BarnesHut,
+    Cutoff,
+    Pairwise,
+    Ewald,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum SofteningArg {
+    None,
+    Plummer,
+    Spline,
+    HardCore,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum CollisionArg {
+    None,
+    Bounce,
+    Merge,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum OutputFormatArg {
+    Csv,
+    Columnar,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum IntegratorArg {
+    SemiImplicitEuler,
+    VelocityVerlet,
+    Leapfrog,
+    Rk4,
+    Boris,
+    Adaptive,
+}
+
+fn parse_boundary(s: &str) -> Result<[BoundaryCondition; 3], String> {
+    let conditions = s
+        .split(',')
+        .map(|v| match v.trim() {
+            "periodic" => Ok(BoundaryCondition::Periodic),
+            "reflective" => Ok(BoundaryCondition::Reflective),
+            "absorbing" => Ok(BoundaryCondition::Absorbing),
+            "open" => Ok(BoundaryCondition::Open),
+            v => Err(format!("unknown boundary condition {v:?}")),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match conditions[..] {
+        [c] => Ok([c; 3]),
+        [x, y, z] => Ok([x, y, z]),
+        _ => Err(format!("expected 1 or 3 values, got {}", conditions.len())),
+    }
+}
+
+fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f32>().map_err(|e| format!("{v:?}: {e}")))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match values[..] {
+        [v] => Ok(Vec3::splat(v)),
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(format!("expected 1 or 3 values, got {}", values.len())),
+    }
+}
+
+impl Cli {
+    /// Settings given by the arguments, as a scenario without particles
+    fn settings(&self) -> Scenario {
+        let backend = match self.backend {
+            BackendArg::Direct => ForceBackend::Direct,
+            BackendArg::BarnesHut => ForceBackend::BarnesHut { theta: self.theta },
+            BackendArg::Cutoff => ForceBackend::Cutoff {
+                radius: self.cutoff,
+            },
+            BackendArg::Pairwise => ForceBackend::Pairwise,
+            BackendArg::Ewald => ForceBackend::Ewald {
+                alpha: self.ewald_alpha,
+                cutoff: self.ewald_cutoff,
+                k_max: self.ewald_k_max,
+            },
+        };
+        let length = self.softening_length;
+        let softening = match self.softening {
+            SofteningArg::None => Softening::None,
+            SofteningArg::Plummer => Softening::Plummer { epsilon: length },
+            SofteningArg::Spline => Softening::Spline { epsilon: length },
+            SofteningArg::HardCore => Softening::HardCore { radius: length },
+        };
+        let integrator = match self.integrator {
+            IntegratorArg::SemiImplicitEuler => Integrator::SemiImplicitEuler,
+            IntegratorArg::VelocityVerlet => Integrator::VelocityVerlet,
+            IntegratorArg::Leapfrog => Integrator::Leapfrog,
+            IntegratorArg::Rk4 => Integrator::Rk4,
+            IntegratorArg::Boris => Integrator::Boris,
+            IntegratorArg::Adaptive => Integrator::Adaptive {
+                max_level: self.max_level,
+                eta: self.eta,
+            },
+        };
+
+        Scenario {
+            seed: self.seed,
+            boundary: Some(Boundary {
+                size: self.size,
+                conditions: self.boundary,
+            }),
+            timestep: Some(Timestep {
+                step: self.timestep,
+                substeps: self.substeps,
+            }),
+            integrator: Some(integrator),
+            dynamics: Some(if self.newtonian {
+                Dynamics::Newtonian
+            } else {
+                Dynamics::Relativistic
+            }),
+            speed_of_light: Some(self.speed_of_light),
+            backend: Some(backend),
+            law: Some(ForceLaw { softening }),
+            annihilation: Some(Annihilation {
+                capture_distance: self.capture_distance,
+                photons: !self.no_photons,
+            }),
+            strong: Some(StrongForce {
+                coupling: self.strong_coupling,
+                string_tension: self.string_tension,
+                range: self.strong_range,
+            }),
+            collisions: Some(Collisions {
+                mode: match self.collisions {
+                    CollisionArg::None => CollisionMode::None,
+                    CollisionArg::Bounce => CollisionMode::Bounce,
+                    CollisionArg::Merge => CollisionMode::Merge,
+                },
+                restitution: self.restitution,
+            }),
+            field: Some(ExternalField {
+                electric: self.electric_field,
+                magnetic: self.magnetic_field,
+            }),
+            magnetic_interaction: Some(if self.magnetic_interaction {
+                MagneticInteraction::BiotSavart
+            } else {
+                MagneticInteraction::None
+            }),
+            bound_states: Some(BoundStateDetection {
+                link_distance: self.bound_distance,
+                ..default()
+            }),
+            drift_threshold: Some(self.drift_threshold),
+            diagnostics: Some(DiagnosticsSettings {
+                enabled: !self.no_diagnostics,
+                interval: self.diagnostics_interval,
+            }),
+            output: Some(Output {
+                path: self.output.clone(),
+                format: match self.output_format {
+                    OutputFormatArg::Csv => OutputFormat::Csv,
+                    OutputFormatArg::Columnar => OutputFormat::Columnar,
+                },
+                interval: self.output_interval,
+            }),
+            trajectory: Some(Trajectory {
+                path: self.trajectory.clone(),
+                interval: self.trajectory_interval,
+            }),
+            checkpoint: Some(Checkpointing {
+                path: self.checkpoint.clone(),
+                interval: self.checkpoint_interval,
+            }),
+            ..default()
+        }
+    }
+
+    /// Inserts the resources configured by the arguments and the scenario file
+    pub fn insert_resources(&self, app: &mut App) -> Result<(), ScenarioError> {
+        // the arguments go through the same checks as the settings of a scenario
+        let settings = self.settings();
+        settings.validate().map_err(|error| match error {
+            ScenarioError::Invalid { field, message } => ScenarioError::Argument { field, message },
+            error => error,
+        })?;
+
+        let mut scenario = match &self.scenario {
+            Some(path) => Scenario::load(path)?,
+            None => Scenario::uniform([
+                ("electron", self.electrons),
+                ("up_quark", self.up_quarks),
+                ("down_quark", self.down_quarks),
+                ("positron", self.positrons),
+                ("anti_up_quark", self.anti_up_quarks),
+                ("anti_down_quark", self.anti_down_quarks),
+            ]),
+        };
+        if let Some(path) = &self.initial_frame {
+            scenario.distributions.clear();
+            scenario.particles = read_frame(path, self.frame)?;
+        }
+
+        // the speeds of the particles can only be checked against the speed of
+        // light once the settings are known, and the rest is valid already
+        let scenario = scenario.or(settings);
+        scenario
+            .validate()
+            .map_err(|error| match &self.initial_frame {
+                Some(_) => ScenarioError::Frame {
+                    line: None,
+                    message: error.to_string(),
+                },
+                None => error,
+            })?;
+        app.insert_resource(Seed(scenario.seed.unwrap_or_else(rand::random)))
+            .insert_resource(if self.three_d {
+                Dimensions::Three
+            } else {
+                Dimensions::Two
+            });
+        scenario.insert_resources(app);
+        app.insert_resource(scenario.registry())
+            .insert_resource(scenario);
+
+        if let Some(path) = &self.restart {
+            Checkpoint::load(path)?.insert_resources(app);
+        }
+        Ok(())
+    }
+}
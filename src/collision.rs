@@ -0,0 +1,238 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    boundary::Boundary,
+    force::pair_softening,
+    grid::{Grid, Indexed},
+    particle::{
+        Dimensions, Dynamics, Mass, Momentum, Particle, Radius, SpeedOfLight, StepForces, Velocity,
+    },
+    species::{SpeciesId, SpeciesRegistry},
+    strong::ColorCharge,
+};
+
+/// How overlapping particles are resolved
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CollisionMode {
+    /// Particles pass through each other
+    #[default]
+    None,
+    /// Particles bounce off each other like hard spheres
+    Bounce,
+    /// Particles stick together into one composite particle, conserving momentum.
+    /// Energy is conserved with relativistic dynamics, where the kinetic energy
+    /// lost goes into the rest mass, but not with Newtonian dynamics
+    Merge,
+}
+
+/// Settings of the collisions between particles
+#[derive(Resource, Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Collisions {
+    pub mode: CollisionMode,
+    /// ratio of the relative normal speed after and before a bounce, 1 for
+    /// elastic and 0 for perfectly inelastic collisions
+    pub restitution: f32,
+}
+
+impl Default for Collisions {
+    fn default() -> Self {
+        Self {
+            mode: CollisionMode::None,
+            restitution: 1.0,
+        }
+    }
+}
+
+/// Collisions since the start of the simulation
+#[derive(Resource, Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct CollisionStats {
+    /// number of bounces
+    pub bounces: u64,
+    /// number of merged pairs
+    pub merges: u64,
+}
+
+/// Components of a massive particle that can collide
+struct Collider<'a> {
+    entity: Entity,
+    particle: Mut<'a, Particle>,
+    radius: Mut<'a, Radius>,
+    transform: Mut<'a, Transform>,
+    momentum: Mut<'a, Momentum>,
+    velocity: Mut<'a, Velocity>,
+    /// inertia of the particle, the sum of both after a merge
+    mass: f32,
+    species: Mut<'a, SpeciesId>,
+}
+
+/// Resolves the overlapping pairs of massive particles, found through a spatial
+/// hash grid, in a fixed order so the result is deterministic.
+///
+/// Bounces exchange an impulse along the line between the centres, treating the
+/// [`Mass`] as the inertia, and push the particles apart so they no longer overlap.
+/// Merging keeps the heavier particle with the summed charge, momentum and
+/// energy, and the volume of both. It becomes a body of the `composite` species
+/// without colour charge, so it no longer annihilates, feels the strong force or
+/// counts as its original species.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn collision_update(
+    mut commands: Commands,
+    mut query: Query<(
+        Entity,
+        &mut Particle,
+        &mut Radius,
+        &mut Transform,
+        &mut Momentum,
+        &mut Velocity,
+        &Mass,
+        &mut SpeciesId,
+    )>,
+    collisions: Res<Collisions>,
+    registry: Res<SpeciesRegistry>,
+    mut stats: ResMut<CollisionStats>,
+    dynamics: Res<Dynamics>,
+    c: Res<SpeedOfLight>,
+    boundary: Res<Boundary>,
+    dimensions: Res<Dimensions>,
+    mut forces: ResMut<StepForces>,
+) {
+    if collisions.mode == CollisionMode::None {
+        return;
+    }
+    // massless particles pass through everything
+    let mut colliders = query
+        .iter_mut()
+        .filter(|(_, particle, ..)| particle.mass > 0.0)
+        .map(
+            |(entity, particle, radius, transform, momentum, velocity, mass, species)| Collider {
+                entity,
+                particle,
+                radius,
+                transform,
+                momentum,
+                velocity,
+                mass: mass.0,
+                species,
+            },
+        )
+        .collect::<Vec<_>>();
+    let Some(max_radius) = colliders
+        .iter()
+        .map(|collider| collider.radius.0)
+        .reduce(f32::max)
+    else {
+        return;
+    };
+
+    let domain = boundary.periodic_box();
+    let grid = Grid::new(
+        colliders
+            .iter()
+            .enumerate()
+            .map(|(index, collider)| Indexed {
+                index,
+                translation: collider.transform.translation,
+            }),
+        max_radius * 2.0,
+        domain,
+    );
+    let mut pairs = Vec::new();
+    for (i, collider) in colliders.iter().enumerate() {
+        grid.for_each_neighbour(
+            collider.transform.translation,
+            max_radius * 2.0,
+            |diff, other| {
+                let j = other.index;
+                if j > i && diff.length() < collider.radius.0 + colliders[j].radius.0 {
+                    pairs.push((i, j));
+                }
+            },
+        );
+    }
+    pairs.sort_unstable();
+    if !pairs.is_empty() {
+        forces.clear();
+    }
+
+    let composite = registry
+        .find("composite")
+        .expect("the standard species should include composite bodies");
+    let mut merged = vec![false; colliders.len()];
+    for (i, j) in pairs {
+        if merged[i] || merged[j] {
+            continue;
+        }
+        // the pairs are ordered with i < j
+        let (before, after) = colliders.split_at_mut(j);
+        let (a, b) = (&mut before[i], &mut after[0]);
+        // the earlier resolutions may have moved the particles apart
+        let diff = domain.displacement(a.transform.translation - b.transform.translation);
+        let dist = diff.length();
+        let overlap = a.radius.0 + b.radius.0 - dist;
+        if overlap <= 0.0 || dist <= f32::EPSILON {
+            continue;
+        }
+        let normal = diff / dist;
+        let (mi, mj) = (a.mass, b.mass);
+
+        match collisions.mode {
+            CollisionMode::None => unreachable!(),
+            CollisionMode::Bounce => {
+                let approach = (a.velocity.0 - b.velocity.0).dot(normal);
+                if approach < 0.0 {
+                    let impulse =
+                        normal * (-(1.0 + collisions.restitution) * approach * mi * mj / (mi + mj));
+                    a.momentum.0 += impulse;
+                    b.momentum.0 -= impulse;
+                    for collider in [&mut *a, &mut *b] {
+                        collider.velocity.0 =
+                            dynamics.velocity(*c, collider.particle.mass, collider.momentum.0);
+                    }
+                    stats.bounces += 1;
+                }
+                // the lighter particle moves further
+                a.transform.translation += normal * (overlap * mj / (mi + mj));
+                b.transform.translation -= normal * (overlap * mi / (mi + mj));
+            }
+            CollisionMode::Merge => {
+                let (keep, gone, gone_index) = if mj > mi { (b, a, i) } else { (a, b, j) };
+                let other = *gone.particle;
+                let other_momentum = gone.momentum.0;
+                let offset =
+                    domain.displacement(gone.transform.translation - keep.transform.translation);
+
+                let energy = dynamics.energy(*c, keep.particle.mass, keep.momentum.0)
+                    + dynamics.energy(*c, other.mass, other_momentum);
+                keep.momentum.0 += other_momentum;
+                keep.particle.mass = match *dynamics {
+                    // the rest mass includes the kinetic energy in the centre of momentum
+                    // frame, and is never below the sum of both, which rounding could cause
+                    Dynamics::Relativistic => ((energy * energy
+                        - keep.momentum.length_squared() * c.0 * c.0)
+                        .max(0.0)
+                        .sqrt()
+                        / (c.0 * c.0))
+                        .max(keep.particle.mass + other.mass),
+                    Dynamics::Newtonian => keep.particle.mass + other.mass,
+                };
+                keep.particle.charge += other.charge;
+                keep.particle.softening = pair_softening(keep.particle.softening, other.softening);
+                keep.radius.0 = match *dimensions {
+                    Dimensions::Two => keep.radius.0.hypot(gone.radius.0),
+                    Dimensions::Three => (keep.radius.0.powi(3) + gone.radius.0.powi(3)).cbrt(),
+                };
+                keep.transform.translation += offset * (gone.mass / (mi + mj));
+                keep.velocity.0 = dynamics.velocity(*c, keep.particle.mass, keep.momentum.0);
+                keep.mass = mi + mj;
+                *keep.species = composite;
+                commands.entity(keep.entity).remove::<ColorCharge>();
+
+                merged[gone_index] = true;
+                commands.entity(gone.entity).despawn();
+                stats.merges += 1;
+            }
+        }
+    }
+}
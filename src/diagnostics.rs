@@ -0,0 +1,196 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    boundary::Boundary,
+    field::ExternalField,
+    force::{potential_energy, ForceBackend, ForceLaw},
+    output::Output,
+    particle::{Dynamics, Mass, Momentum, Particle, SimulationClock, SpeedOfLight, Velocity},
+    strong::{strong_potential_energy, ColorCharge, StrongForce},
+};
+
+/// Relative energy drift above which a warning is logged
+#[derive(Resource, Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct DriftThreshold(pub f64);
+
+impl Default for DriftThreshold {
+    fn default() -> Self {
+        Self(0.01)
+    }
+}
+
+/// Settings of the [`Diagnostics`], which cost about as much as a force
+/// evaluation and are computed at the start and every `interval` steps after
+#[derive(Resource, Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiagnosticsSettings {
+    /// without diagnostics they are only computed for the samples of the [`Output`]
+    pub enabled: bool,
+    /// number of integration steps between diagnostics
+    pub interval: u64,
+}
+
+impl Default for DiagnosticsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: 100,
+        }
+    }
+}
+
+/// Whether the diagnostics are due at the current step, either by their
+/// settings or because the output takes a sample
+pub fn diagnostics_due(
+    settings: Res<DiagnosticsSettings>,
+    output: Res<Output>,
+    clock: Res<SimulationClock>,
+) -> bool {
+    (settings.enabled && clock.steps.is_multiple_of(settings.interval.max(1)))
+        || (output.path.is_some() && clock.steps.is_multiple_of(output.interval.max(1)))
+}
+
+/// Conserved quantities of the particles, computed as set by [`DiagnosticsSettings`]
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct Diagnostics {
+    /// step the diagnostics were last computed at
+    pub step: Option<u64>,
+    /// number of particles
+    pub particles: usize,
+    /// kinetic energy in electronvolts
+    pub kinetic_energy: f64,
+    /// Coulomb potential energy in electronvolts, with the approximations of
+    /// the [`ForceBackend`]
+    pub potential_energy: f64,
+    /// potential energy of the strong force in electronvolts
+    pub strong_energy: f64,
+    /// potential energy in the external electric field in electronvolts
+    pub field_energy: f64,
+    /// total momentum in eV/c
+    pub momentum: Vec3,
+    /// total angular momentum around the origin in eV/c * m * k_e / e
+    pub angular_momentum: Vec3,
+    /// centre of mass, weighted by the [`Mass`]
+    pub center_of_mass: Vec3,
+    /// total energy when the diagnostics were first computed
+    pub initial_energy: Option<f64>,
+    /// drift of the last warning, so warnings are only repeated when it doubles
+    warned_drift: f64,
+}
+
+impl Diagnostics {
+    /// Diagnostics of a restarted simulation, measuring the drift from the
+    /// energy at the start of the original run
+    pub fn resumed(initial_energy: Option<f64>) -> Self {
+        Self {
+            initial_energy,
+            ..default()
+        }
+    }
+
+    /// Kinetic and potential energy in electronvolts
+    pub fn energy(&self) -> f64 {
+        self.kinetic_energy + self.potential_energy + self.strong_energy + self.field_energy
+    }
+
+    /// Change of the energy since the start, relative to the initial energy.
+    ///
+    /// Besides integration errors, annihilation, absorbing walls and merging
+    /// collisions change the energy too.
+    pub fn drift(&self) -> f64 {
+        match self.initial_energy {
+            Some(initial) if initial != 0.0 => (self.energy() - initial) / initial.abs(),
+            _ => 0.0,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn diagnostics_update(
+    query: Query<(
+        &Particle,
+        Option<&ColorCharge>,
+        &Transform,
+        &Momentum,
+        &Velocity,
+        &Mass,
+    )>,
+    mut diagnostics: ResMut<Diagnostics>,
+    threshold: Res<DriftThreshold>,
+    clock: Res<SimulationClock>,
+    dynamics: Res<Dynamics>,
+    c: Res<SpeedOfLight>,
+    backend: Res<ForceBackend>,
+    law: Res<ForceLaw>,
+    strong: Res<StrongForce>,
+    field: Res<ExternalField>,
+    boundary: Res<Boundary>,
+) {
+    let domain = boundary.periodic_box();
+    let particles = query
+        .iter()
+        .map(|(&particle, color, ..)| (particle, color.copied()))
+        .collect::<Vec<_>>();
+    let translations = query
+        .iter()
+        .map(|(_, _, transform, ..)| transform.translation)
+        .collect::<Vec<_>>();
+
+    let mut kinetic_energy = 0.0;
+    let mut field_energy = 0.0;
+    let mut momentum = Vec3::ZERO;
+    let mut angular_momentum = Vec3::ZERO;
+    let mut weighted = Vec3::ZERO;
+    let mut total_mass = 0.0;
+    for (particle, _, transform, p, v, mass) in query.iter() {
+        let mass_energy = (mass.0 * c.0 * c.0) as f64;
+        let beta_squared = (v.length_squared() / (c.0 * c.0)) as f64;
+        kinetic_energy += match *dynamics {
+            // Mc² minus the rest energy Mc² / γ, written to avoid cancellation at low speeds
+            Dynamics::Relativistic => {
+                let beta_squared = beta_squared.min(1.0);
+                mass_energy * beta_squared / (1.0 + (1.0 - beta_squared).sqrt())
+            }
+            Dynamics::Newtonian if particle.mass == 0.0 => {
+                dynamics.energy(*c, particle.mass, p.0) as f64
+            }
+            Dynamics::Newtonian => 0.5 * mass_energy * beta_squared,
+        };
+        field_energy -= (particle.charge * field.electric.dot(transform.translation)) as f64;
+        momentum += p.0;
+        angular_momentum += transform.translation.cross(p.0);
+        weighted += transform.translation * mass.0;
+        total_mass += mass.0;
+    }
+
+    *diagnostics = Diagnostics {
+        step: Some(clock.steps),
+        particles: particles.len(),
+        kinetic_energy,
+        potential_energy: potential_energy(*backend, &law, &domain, &particles, &translations),
+        strong_energy: strong_potential_energy(&strong, &law, &domain, &particles, &translations),
+        field_energy,
+        momentum,
+        angular_momentum,
+        center_of_mass: if total_mass > 0.0 {
+            weighted / total_mass
+        } else {
+            Vec3::ZERO
+        },
+        ..*diagnostics
+    };
+    if diagnostics.initial_energy.is_none() {
+        diagnostics.initial_energy = Some(diagnostics.energy());
+    }
+
+    let drift = diagnostics.drift().abs();
+    if drift > threshold.0 && drift > diagnostics.warned_drift * 2.0 {
+        warn!(
+            "relative energy drift of {:.3e}, above the threshold of {:.3e}",
+            diagnostics.drift(),
+            threshold.0
+        );
+        diagnostics.warned_drift = drift;
+    }
+}
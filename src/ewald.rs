@@ -0,0 +1,197 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    force::{pair_softening, Body, ForceLaw, PeriodicBox},
+    grid::Grid,
+};
+
+/// Length of the box along non-periodic axes, as a multiple of its size,
+/// so the periodic images of a slab are separated by vacuum
+const SLAB_PADDING: f32 = 3.0;
+
+/// Wave vector of the reciprocal lattice
+struct Wave {
+    k: Vec3,
+    /// (4π / V) exp(-k² / 4α²) / k²
+    weight: f32,
+    /// real part of the structure factor, Σ q cos(k·r)
+    cos: f32,
+    /// imaginary part of the structure factor, Σ q sin(k·r)
+    sin: f32,
+}
+
+/// Ewald summation of the field of all bodies and their periodic images.
+///
+/// The field is split into a short range part summed in real space within a
+/// cutoff, and a smooth long range part summed over the reciprocal lattice.
+/// Non-periodic axes are treated as a slab padded with vacuum, with the dipole
+/// correction of Yeh & Berkowitz.
+pub struct Ewald {
+    alpha: f32,
+    cutoff: f32,
+    grid: Grid,
+    waves: Vec<Wave>,
+    /// field of the total dipole moment along the non-periodic axes
+    dipole_field: Vec3,
+    /// potential of the uniform background that neutralises the total charge
+    background: f32,
+}
+
+impl Ewald {
+    /// `alpha` is the splitting parameter, the real space part decays as
+    /// erfc(alpha * r) and is cut off at `cutoff`, the reciprocal part uses
+    /// wave vectors up to `k_max` along every periodic axis.
+    pub fn new(
+        bodies: Vec<Body>,
+        domain: PeriodicBox,
+        alpha: f32,
+        cutoff: f32,
+        k_max: u32,
+    ) -> Self {
+        let lengths = Vec3::select(domain.periodic, domain.size, domain.size * SLAB_PADDING);
+        let volume = lengths.x * lengths.y * lengths.z;
+        // the padded axes need proportionally more wave vectors for the same resolution
+        let k_max = IVec3::select(
+            domain.periodic,
+            IVec3::splat(k_max as i32),
+            IVec3::splat((k_max as f32 * SLAB_PADDING).ceil() as i32),
+        );
+
+        let waves = (-k_max.x..=k_max.x)
+            .into_par_iter()
+            .flat_map_iter(|x| {
+                (-k_max.y..=k_max.y)
+                    .flat_map(move |y| (-k_max.z..=k_max.z).map(move |z| IVec3::new(x, y, z)))
+            })
+            .filter(|&n| n != IVec3::ZERO)
+            .map(|n| {
+                let k = n.as_vec3() * 2.0 * PI / lengths;
+                let k_squared = k.length_squared();
+                let (cos, sin) = bodies.iter().fold((0.0, 0.0), |(cos, sin), body| {
+                    let phase = k.dot(body.translation);
+                    (
+                        cos + body.charge * phase.cos(),
+                        sin + body.charge * phase.sin(),
+                    )
+                });
+                Wave {
+                    k,
+                    weight: 4.0 * PI / volume * (-k_squared / (4.0 * alpha * alpha)).exp()
+                        / k_squared,
+                    cos,
+                    sin,
+                }
+            })
+            .collect();
+
+        let dipole = bodies
+            .iter()
+            .map(|body| body.translation * body.charge)
+            .sum::<Vec3>();
+        let dipole_field = Vec3::select(domain.periodic, Vec3::ZERO, dipole * (-4.0 * PI / volume));
+        let charge = bodies.iter().map(|body| body.charge).sum::<f32>();
+
+        Self {
+            alpha,
+            cutoff,
+            grid: Grid::new(bodies.into_iter(), cutoff, domain),
+            waves,
+            dipole_field,
+            background: -PI * charge / (volume * alpha * alpha),
+        }
+    }
+
+    /// Partially calculated force on a unit charge at `translation` with
+    /// `softening`, see [`ForceLaw::semi_force`].
+    ///
+    /// Softening only affects the real space part, which is the field of
+    /// `law` minus the long range part of Coulomb's law.
+    pub fn semi_force(&self, law: &ForceLaw, translation: Vec3, softening: Option<f32>) -> Vec3 {
+        let alpha = self.alpha;
+        let gaussian = 2.0 * alpha / PI.sqrt();
+
+        let real = self
+            .grid
+            .sum_neighbours(translation, self.cutoff, |diff, body| {
+                let dist_squared = diff.length_squared();
+                if dist_squared <= f32::EPSILON {
+                    return Vec3::ZERO;
+                }
+                let dist = dist_squared.sqrt();
+                let x = alpha * dist;
+
+                // long range field divided by the distance, (erf(αr)/r² - 2α/√π exp(-α²r²)/r) / r
+                let long_range = if x < 0.05 {
+                    // limit for small distances, where the difference loses all precision
+                    2.0 * gaussian * alpha * alpha / 3.0
+                } else {
+                    (erf(x) / dist_squared - gaussian * (-x * x).exp() / dist) / dist
+                };
+
+                law.semi_force(diff, body.charge, pair_softening(softening, body.softening))
+                    - diff * (body.charge * long_range)
+            });
+
+        let reciprocal = self
+            .waves
+            .iter()
+            .map(|wave| {
+                let phase = wave.k.dot(translation);
+                wave.k * (wave.weight * (phase.sin() * wave.cos - phase.cos() * wave.sin))
+            })
+            .sum::<Vec3>();
+
+        real + reciprocal + self.dipole_field
+    }
+
+    /// Potential of a unit charge at `translation` with `softening`, see
+    /// [`ForceLaw::potential`], including the neutralising background and the
+    /// dipole correction.
+    ///
+    /// A body at `translation` contributes the potential of `law` at distance 0
+    /// minus the potential of its own Gaussian screening charge, the latter
+    /// being the Ewald self energy.
+    pub fn potential(&self, law: &ForceLaw, translation: Vec3, softening: Option<f32>) -> f32 {
+        let alpha = self.alpha;
+        let gaussian = 2.0 * alpha / PI.sqrt();
+
+        let real = self
+            .grid
+            .sum_neighbours(translation, self.cutoff, |diff, body| {
+                let dist = diff.length();
+                let x = alpha * dist;
+
+                // long range potential erf(αr)/r
+                let long_range = if x < 0.05 {
+                    gaussian * (1.0 - x * x / 3.0)
+                } else {
+                    erf(x) / dist
+                };
+
+                law.potential(dist, body.charge, pair_softening(softening, body.softening))
+                    - body.charge * long_range
+            });
+
+        let reciprocal = self
+            .waves
+            .iter()
+            .map(|wave| {
+                let phase = wave.k.dot(translation);
+                wave.weight * (phase.cos() * wave.cos + phase.sin() * wave.sin)
+            })
+            .sum::<f32>();
+
+        real + reciprocal + self.background - self.dipole_field.dot(translation)
+    }
+}
+
+/// Error function, with an absolute error below 1.5e-7 (Abramowitz & Stegun 7.1.26)
+fn erf(x: f32) -> f32 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly =
+        t * (0.2548296 + t * (-0.28449672 + t * (1.4214138 + t * (-1.4531521 + t * 1.0614054))));
+    (1.0 - poly * (-x * x).exp()).copysign(x)
+}
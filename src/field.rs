@@ -0,0 +1,87 @@
+use bevy::prelude::*;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    force::{pair_softening, ForceLaw, PeriodicBox},
+    integrator::Force,
+    particle::{Particle, SpeedOfLight},
+    strong::ColorCharge,
+};
+
+/// Uniform electric and magnetic fields filling the whole box
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExternalField {
+    /// electric field in units of the field of an elementary charge
+    #[serde(default)]
+    pub electric: Vec3,
+    /// magnetic field in the same units, divided by the speed of light
+    #[serde(default)]
+    pub magnetic: Vec3,
+}
+
+/// Magnetic interaction between moving charges
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MagneticInteraction {
+    /// Only the external magnetic field acts on the particles
+    #[default]
+    None,
+    /// Every moving charge creates the field q v × r / (c² r³) of the Biot–Savart
+    /// law, the magnetic part of the Darwin approximation. Exact summation, O(N²)
+    BiotSavart,
+}
+
+/// Lorentz forces on the `particles` at `indices`, given the `forces` on them
+/// that do not depend on the fields, when all particles are placed at
+/// `translations` and move at `velocities`
+#[allow(clippy::too_many_arguments)]
+pub fn lorentz_forces(
+    field: &ExternalField,
+    interaction: MagneticInteraction,
+    c: SpeedOfLight,
+    law: &ForceLaw,
+    domain: &PeriodicBox,
+    particles: &[(Particle, Option<ColorCharge>)],
+    translations: &[Vec3],
+    velocities: &[Vec3],
+    indices: &[usize],
+    forces: Vec<Vec3>,
+) -> Vec<Force> {
+    // moving charges, the sources of the magnetic field
+    let currents = match interaction {
+        MagneticInteraction::None => Vec::new(),
+        MagneticInteraction::BiotSavart => (0..particles.len())
+            .filter(|&j| particles[j].0.charge != 0.0 && velocities[j] != Vec3::ZERO)
+            .collect(),
+    };
+
+    indices
+        .par_iter()
+        .zip(forces)
+        .map(|(&i, force)| {
+            let (particle, _) = particles[i];
+            // massless particles move in straight lines
+            if particle.mass == 0.0 || particle.charge == 0.0 {
+                return Force { force, ..default() };
+            }
+            let induced = currents
+                .iter()
+                .filter(|&&j| j != i)
+                .map(|&j| {
+                    let semi_force = law.semi_force(
+                        domain.displacement(translations[i] - translations[j]),
+                        particles[j].0.charge,
+                        pair_softening(particle.softening, particles[j].0.softening),
+                    );
+                    velocities[j].cross(semi_force)
+                })
+                .sum::<Vec3>()
+                / (c.0 * c.0);
+            Force {
+                force: force + field.electric * particle.charge,
+                magnetic: (field.magnetic + induced) * particle.charge,
+            }
+        })
+        .collect()
+}
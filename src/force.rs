@@ -0,0 +1,580 @@
+use bevy::prelude::*;
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ewald::Ewald,
+    grid::Grid,
+    octree::Octree,
+    particle::Particle,
+    strong::{strong_forces, ColorCharge, StrongForce},
+};
+
+/// Number of blocks of bodies the pairwise backend splits the pairs into, even
+/// and fixed so that the summation order is the same on every machine
+const PAIRWISE_BLOCKS: usize = 64;
+
+/// Box the particles are simulated in, wrapping around along some of its axes
+#[derive(Clone, Copy, Debug)]
+pub struct PeriodicBox {
+    /// lower corner of the box
+    pub min: Vec3,
+    /// side lengths of the box
+    pub size: Vec3,
+    /// axes along which the box wraps around
+    pub periodic: BVec3,
+}
+
+impl PeriodicBox {
+    /// Shortest displacement between two points given their difference,
+    /// following the minimum image convention along the periodic axes
+    pub fn displacement(&self, diff: Vec3) -> Vec3 {
+        let wrapped = diff - self.size * (diff / self.size).round();
+        Vec3::select(self.periodic, wrapped, diff)
+    }
+}
+
+/// A point charge, snapshotted from the particles for the force calculation
+#[derive(Clone, Copy)]
+pub struct Body {
+    pub translation: Vec3,
+    /// charge in elementary charges
+    pub charge: f32,
+    /// softening length of the particle, overriding the one of the [`Softening`] kernel
+    pub softening: Option<f32>,
+}
+
+/// Softening length used between two particles, the larger of the two
+pub fn pair_softening(a: Option<f32>, b: Option<f32>) -> Option<f32> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Kernel that replaces the singular 1/r² of Coulomb's law at short distances
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum Softening {
+    /// Unmodified Coulomb's law
+    None,
+    /// Plummer sphere, 1/r² becomes r/(r² + ε²)^(3/2)
+    Plummer { epsilon: f32 },
+    /// Cubic spline of Monaghan & Lattanzio, exactly Coulombic beyond `epsilon`
+    Spline { epsilon: f32 },
+    /// Force stays constant within `radius`, as if the particles were `radius` apart
+    HardCore { radius: f32 },
+}
+
+/// Interaction between the particles
+#[derive(Resource, Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct ForceLaw {
+    pub softening: Softening,
+}
+
+impl Default for ForceLaw {
+    fn default() -> Self {
+        Self {
+            softening: Softening::Plummer { epsilon: 1.0 },
+        }
+    }
+}
+
+impl ForceLaw {
+    /// Partially calculated force on a unit charge at a displacement of `diff`
+    /// from a particle with `charge`, using Coulomb's law.
+    ///
+    /// `softening` replaces the length of the [`Softening`] kernel when set.
+    pub fn semi_force(&self, diff: Vec3, charge: f32, softening: Option<f32>) -> Vec3 {
+        let dist_squared = diff.length_squared();
+
+        if dist_squared <= f32::EPSILON {
+            return Vec3::ZERO;
+        }
+        let dist = dist_squared.sqrt();
+
+        // force divided by the distance, so the direction does not need normalising
+        let factor = match self.softening {
+            Softening::None => 1.0 / (dist_squared * dist),
+            Softening::Plummer { epsilon } => {
+                let epsilon = softening.unwrap_or(epsilon);
+                (dist_squared + epsilon * epsilon).powf(-1.5)
+            }
+            Softening::Spline { epsilon } => {
+                let h = softening.unwrap_or(epsilon);
+                let u = dist / h;
+                if u < 0.5 {
+                    (32.0 / 3.0 + u * u * (32.0 * u - 38.4)) / (h * h * h)
+                } else if u < 1.0 {
+                    (64.0 / 3.0 - 48.0 * u + 38.4 * u * u
+                        - 32.0 / 3.0 * u * u * u
+                        - 1.0 / 15.0 / (u * u * u))
+                        / (h * h * h)
+                } else {
+                    1.0 / (dist_squared * dist)
+                }
+            }
+            Softening::HardCore { radius } => {
+                let radius = softening.unwrap_or(radius);
+                1.0 / (dist * dist.max(radius).powi(2))
+            }
+        };
+        diff * (charge * factor)
+    }
+    /// Potential energy of a unit charge at a distance of `dist` from a particle
+    /// with `charge`, matching [`semi_force`](Self::semi_force) for every kernel
+    pub fn potential(&self, dist: f32, charge: f32, softening: Option<f32>) -> f32 {
+        let potential = match self.softening {
+            Softening::None if dist <= f32::EPSILON => 0.0,
+            Softening::None => 1.0 / dist,
+            Softening::Plummer { epsilon } => {
+                let epsilon = softening.unwrap_or(epsilon);
+                1.0 / (dist * dist + epsilon * epsilon).sqrt()
+            }
+            Softening::Spline { epsilon } => {
+                let h = softening.unwrap_or(epsilon);
+                let u = dist / h;
+                if u < 0.5 {
+                    (2.8 - u * u * (16.0 / 3.0 - u * u * (9.6 - 6.4 * u))) / h
+                } else if u < 1.0 {
+                    (3.2 - 1.0 / (15.0 * u)
+                        - u * u * (32.0 / 3.0 - u * (16.0 - u * (9.6 - 32.0 / 15.0 * u))))
+                        / h
+                } else {
+                    1.0 / dist
+                }
+            }
+            Softening::HardCore { radius } => {
+                let radius = softening.unwrap_or(radius);
+                if dist < radius {
+                    2.0 / radius - dist / (radius * radius)
+                } else {
+                    1.0 / dist
+                }
+            }
+        };
+        charge * potential
+    }
+}
+
+/// Algorithm used to sum the forces between particles
+#[derive(Resource, Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub enum ForceBackend {
+    /// Exact pairwise summation over all particles, O(N²)
+    #[default]
+    Direct,
+    /// Barnes-Hut tree approximation up to the quadrupole moments, O(N log N)
+    BarnesHut {
+        /// opening angle, larger values are faster but less accurate
+        theta: f32,
+    },
+    /// Only particles closer than `radius` interact, found through a spatial hash grid
+    Cutoff { radius: f32 },
+    /// Exact summation visiting every pair once and applying equal and
+    /// opposite forces, so momentum is conserved up to rounding errors
+    Pairwise,
+    /// Ewald summation over all periodic images, see [`Ewald`]
+    Ewald {
+        /// splitting parameter between the real and reciprocal space sums
+        alpha: f32,
+        /// cutoff radius of the real space sum
+        cutoff: f32,
+        /// number of wave vectors along every periodic axis in the reciprocal space sum
+        k_max: u32,
+    },
+}
+
+/// Particles that exert a force, prepared for the selected [`ForceBackend`]
+enum FieldSource {
+    Direct(Vec<Body>),
+    BarnesHut { octree: Octree, theta: f32 },
+    Cutoff { grid: Grid, radius: f32 },
+    Ewald(Ewald),
+}
+
+impl FieldSource {
+    fn new(backend: ForceBackend, domain: PeriodicBox, bodies: Vec<Body>) -> Self {
+        match backend {
+            ForceBackend::Direct | ForceBackend::Pairwise => FieldSource::Direct(bodies),
+            ForceBackend::BarnesHut { theta } => FieldSource::BarnesHut {
+                octree: Octree::new(bodies),
+                theta,
+            },
+            ForceBackend::Cutoff { radius } => FieldSource::Cutoff {
+                grid: Grid::new(bodies.into_iter(), radius, domain),
+                radius,
+            },
+            ForceBackend::Ewald {
+                alpha,
+                cutoff,
+                k_max,
+            } => FieldSource::Ewald(Ewald::new(bodies, domain, alpha, cutoff, k_max)),
+        }
+    }
+
+    fn semi_force(
+        &self,
+        law: &ForceLaw,
+        domain: &PeriodicBox,
+        translation: Vec3,
+        softening: Option<f32>,
+    ) -> Vec3 {
+        match self {
+            FieldSource::Direct(bodies) => bodies
+                .iter()
+                .map(|body| {
+                    law.semi_force(
+                        domain.displacement(translation - body.translation),
+                        body.charge,
+                        pair_softening(softening, body.softening),
+                    )
+                })
+                .sum::<Vec3>(),
+            FieldSource::BarnesHut { octree, theta } => {
+                octree.semi_force(law, domain, translation, softening, *theta)
+            }
+            FieldSource::Cutoff { grid, radius } => {
+                grid.sum_neighbours(translation, *radius, |diff, body| {
+                    law.semi_force(diff, body.charge, pair_softening(softening, body.softening))
+                })
+            }
+            FieldSource::Ewald(ewald) => ewald.semi_force(law, translation, softening),
+        }
+    }
+
+    /// Potential at a body with `charge` and `softening` at `translation`, see
+    /// [`ForceLaw::potential`], without the contribution of the body itself
+    fn potential(
+        &self,
+        law: &ForceLaw,
+        domain: &PeriodicBox,
+        translation: Vec3,
+        charge: f32,
+        softening: Option<f32>,
+    ) -> f32 {
+        let own = law.potential(0.0, charge, softening);
+        match self {
+            FieldSource::Direct(bodies) => {
+                bodies
+                    .iter()
+                    .map(|body| {
+                        law.potential(
+                            domain.displacement(translation - body.translation).length(),
+                            body.charge,
+                            pair_softening(softening, body.softening),
+                        )
+                    })
+                    .sum::<f32>()
+                    - own
+            }
+            FieldSource::BarnesHut { octree, theta } => {
+                octree.potential(law, domain, translation, softening, *theta) - own
+            }
+            // shifted to vanish at the cutoff, so it is continuous like the forces
+            FieldSource::Cutoff { grid, radius } => {
+                grid.sum_neighbours(translation, *radius, |diff, body| {
+                    let softening = pair_softening(softening, body.softening);
+                    law.potential(diff.length(), body.charge, softening)
+                        - law.potential(*radius, body.charge, softening)
+                }) - (own - law.potential(*radius, charge, softening))
+            }
+            FieldSource::Ewald(ewald) => ewald.potential(law, translation, softening) - own,
+        }
+    }
+}
+
+/// Forces on all `bodies`, computed once per pair using Newton's third law
+fn pairwise_forces(law: &ForceLaw, domain: &PeriodicBox, bodies: &[Body]) -> Vec<Vec3> {
+    let n = bodies.len();
+    let block_size = n.div_ceil(PAIRWISE_BLOCKS).max(1);
+    let block = |b: usize| (b * block_size).min(n)..((b + 1) * block_size).min(n);
+
+    // forces between the bodies of blocks `a` and `b`, on the bodies of both
+    let tile = |a: usize, b: usize| {
+        let (rows, columns) = (block(a), block(b));
+        let mut row_forces = vec![Vec3::ZERO; rows.len()];
+        let mut column_forces = vec![Vec3::ZERO; columns.len()];
+        for i in rows.clone() {
+            let b1 = &bodies[i];
+            // within a block every pair is visited once as well
+            let start = if a == b { i + 1 } else { columns.start };
+            for j in start..columns.end {
+                let b2 = &bodies[j];
+                let force = law.semi_force(
+                    domain.displacement(b1.translation - b2.translation),
+                    b2.charge,
+                    pair_softening(b1.softening, b2.softening),
+                ) * b1.charge;
+                row_forces[i - rows.start] += force;
+                if a == b {
+                    row_forces[j - rows.start] -= force;
+                } else {
+                    column_forces[j - columns.start] -= force;
+                }
+            }
+        }
+        (row_forces, column_forces)
+    };
+
+    // the pairs of blocks are scheduled in rounds like a round robin tournament,
+    // so every block appears at most once per round and the partial forces are
+    // only as large as the bodies, while the summation order stays fixed
+    let last = PAIRWISE_BLOCKS - 1;
+    let rounds = (0..last).map(|round| {
+        (0..PAIRWISE_BLOCKS / 2)
+            .map(|k| match k {
+                0 => (round, last),
+                k => ((round + k) % last, (round + last - k) % last),
+            })
+            .collect::<Vec<_>>()
+    });
+    let diagonal = (0..PAIRWISE_BLOCKS).map(|b| (b, b)).collect::<Vec<_>>();
+
+    let mut forces = vec![Vec3::ZERO; n];
+    for pairs in std::iter::once(diagonal).chain(rounds) {
+        let partial = pairs
+            .par_iter()
+            .map(|&(a, b)| tile(a, b))
+            .collect::<Vec<_>>();
+        for (&(a, b), (row_forces, column_forces)) in pairs.iter().zip(partial) {
+            for (force, partial) in forces[block(a)].iter_mut().zip(row_forces) {
+                *force += partial;
+            }
+            if a != b {
+                for (force, partial) in forces[block(b)].iter_mut().zip(column_forces) {
+                    *force += partial;
+                }
+            }
+        }
+    }
+    forces
+}
+
+/// Coulomb potential energy of the `particles` placed at `translations`, with
+/// the same approximations as the forces of `backend`.
+///
+/// The exact backends sum every pair once with the minimum image along the
+/// periodic axes, the others sum the potential at every particle and halve it.
+pub fn potential_energy(
+    backend: ForceBackend,
+    law: &ForceLaw,
+    domain: &PeriodicBox,
+    particles: &[(Particle, Option<ColorCharge>)],
+    translations: &[Vec3],
+) -> f64 {
+    if !matches!(backend, ForceBackend::Direct | ForceBackend::Pairwise) {
+        let source = FieldSource::new(backend, *domain, bodies(particles, translations));
+        let energies = (0..particles.len())
+            .into_par_iter()
+            .map(|i| {
+                let (particle, _) = particles[i];
+                if particle.charge == 0.0 {
+                    return 0.0;
+                }
+                let potential = source.potential(
+                    law,
+                    domain,
+                    translations[i],
+                    particle.charge,
+                    particle.softening,
+                );
+                (potential * particle.charge) as f64 / 2.0
+            })
+            .collect::<Vec<_>>();
+        return energies.into_iter().sum();
+    }
+
+    // the rows are summed in order, so the result does not depend on the number of threads
+    let rows = (0..particles.len())
+        .into_par_iter()
+        .map(|i| {
+            let (p1, _) = particles[i];
+            if p1.charge == 0.0 {
+                return 0.0;
+            }
+            (i + 1..particles.len())
+                .map(|j| {
+                    let (p2, _) = particles[j];
+                    let dist = domain
+                        .displacement(translations[i] - translations[j])
+                        .length();
+                    let softening = pair_softening(p1.softening, p2.softening);
+                    (law.potential(dist, p2.charge, softening) * p1.charge) as f64
+                })
+                .sum::<f64>()
+        })
+        .collect::<Vec<_>>();
+    rows.into_iter().sum()
+}
+
+/// Point charges of the `particles` placed at `translations`
+fn bodies(particles: &[(Particle, Option<ColorCharge>)], translations: &[Vec3]) -> Vec<Body> {
+    particles
+        .iter()
+        .zip(translations)
+        .map(|((particle, _), &translation)| Body {
+            translation,
+            charge: particle.charge,
+            softening: particle.softening,
+        })
+        .collect()
+}
+
+/// Forces on the `particles` at `indices` when all of them are placed at `translations`
+pub fn calculate_forces(
+    backend: ForceBackend,
+    law: &ForceLaw,
+    strong: &StrongForce,
+    domain: &PeriodicBox,
+    particles: &[(Particle, Option<ColorCharge>)],
+    translations: &[Vec3],
+    indices: &[usize],
+) -> Vec<Vec3> {
+    let bodies = bodies(particles, translations);
+    let mut forces = if let ForceBackend::Pairwise = backend {
+        // the forces on a subset are not any cheaper, as all pairs are visited anyway
+        let forces = pairwise_forces(law, domain, &bodies);
+        indices.iter().map(|&i| forces[i]).collect::<Vec<_>>()
+    } else {
+        let source = FieldSource::new(backend, *domain, bodies);
+        indices
+            .par_iter()
+            .map(|&i| {
+                let (properties, _) = particles[i];
+                source.semi_force(law, domain, translations[i], properties.softening)
+                    * properties.charge
+            })
+            .collect()
+    };
+    if let Some(strong) = strong_forces(strong, law, domain, particles, translations, indices) {
+        forces.iter_mut().zip(strong).for_each(|(a, b)| *a += b);
+    }
+
+    // massless particles move in straight lines
+    for (&i, force) in indices.iter().zip(&mut forces) {
+        if particles[i].0.mass == 0.0 {
+            *force = Vec3::ZERO;
+        }
+    }
+    forces
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    const KERNELS: [Softening; 4] = [
+        Softening::None,
+        Softening::Plummer { epsilon: 1.0 },
+        Softening::Spline { epsilon: 1.0 },
+        Softening::HardCore { radius: 1.0 },
+    ];
+
+    /// Distances on both sides of the branches of every kernel
+    const DISTANCES: [f32; 8] = [0.1, 0.3, 0.45, 0.55, 0.8, 0.95, 1.05, 3.0];
+
+    fn field(law: &ForceLaw, dist: f32, softening: Option<f32>) -> f32 {
+        law.semi_force(Vec3::new(dist, 0.0, 0.0), 2.0, softening).x
+    }
+
+    /// Minus the derivative of the potential, by central differences
+    fn gradient(law: &ForceLaw, dist: f32, softening: Option<f32>) -> f32 {
+        let h = 1e-3;
+        -(law.potential(dist + h, 2.0, softening) - law.potential(dist - h, 2.0, softening))
+            / (2.0 * h)
+    }
+
+    #[test]
+    fn force_is_minus_the_gradient_of_the_potential() {
+        for softening in KERNELS {
+            let law = ForceLaw { softening };
+            for dist in DISTANCES {
+                let (field, gradient) = (field(&law, dist, None), gradient(&law, dist, None));
+                assert!(
+                    (field - gradient).abs() <= 1e-3 * field.abs().max(1.0),
+                    "{softening:?} at {dist}: field {field}, gradient {gradient}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn softening_of_the_particles_replaces_the_length() {
+        for softening in &KERNELS[1..] {
+            let law = ForceLaw {
+                softening: *softening,
+            };
+            for dist in DISTANCES {
+                let (field, gradient) = (
+                    field(&law, dist * 2.0, Some(2.0)),
+                    gradient(&law, dist * 2.0, Some(2.0)),
+                );
+                assert!(
+                    (field - gradient).abs() <= 1e-3 * field.abs().max(1.0),
+                    "{softening:?} at {dist}: field {field}, gradient {gradient}"
+                );
+                // the kernels scale with their length
+                let unit = law.potential(dist, 2.0, Some(1.0));
+                let scaled = law.potential(dist * 2.0, 2.0, Some(2.0));
+                assert!((unit - scaled * 2.0).abs() <= 1e-5 * unit.abs());
+            }
+        }
+    }
+
+    #[test]
+    fn potentials_are_continuous_and_coulombic_far_away() {
+        for softening in KERNELS {
+            let law = ForceLaw { softening };
+            for boundary in [0.5, 1.0] {
+                let below = law.potential(boundary - 1e-5, 2.0, None);
+                let above = law.potential(boundary + 1e-5, 2.0, None);
+                assert!(
+                    (below - above).abs() <= 1e-3,
+                    "{softening:?} at {boundary}: {below} and {above}"
+                );
+            }
+            let far = law.potential(100.0, 2.0, None);
+            let tolerance = match softening {
+                Softening::Plummer { .. } => 1e-5,
+                _ => 1e-7,
+            };
+            assert!((far - 0.02).abs() <= tolerance, "{softening:?}: {far}");
+        }
+    }
+
+    #[test]
+    fn pairwise_forces_conserve_momentum() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        // more bodies than blocks, not a multiple of them
+        let bodies = (0..301)
+            .map(|_| Body {
+                translation: Vec3::new(
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..10.0),
+                ),
+                charge: rng.gen_range(-2.0..2.0),
+                softening: rng.gen_bool(0.5).then(|| rng.gen_range(0.1..1.0)),
+            })
+            .collect::<Vec<_>>();
+        let law = ForceLaw {
+            softening: Softening::Plummer { epsilon: 0.5 },
+        };
+        for periodic in [BVec3::FALSE, BVec3::new(true, true, false)] {
+            let domain = PeriodicBox {
+                min: Vec3::splat(-10.0),
+                size: Vec3::splat(20.0),
+                periodic,
+            };
+            let forces = pairwise_forces(&law, &domain, &bodies);
+            let total = forces.iter().sum::<Vec3>();
+            let scale = forces.iter().map(|force| force.length()).sum::<f32>();
+            assert!(
+                total.length() <= 1e-5 * scale,
+                "{periodic:?}: total {total} of {scale}"
+            );
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::ops::AddAssign;
+
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::force::{Body, PeriodicBox};
+
+/// Something with a position, that can be sorted into a [`Grid`]
+pub trait Located {
+    fn translation(&self) -> Vec3;
+}
+
+impl Located for Body {
+    fn translation(&self) -> Vec3 {
+        self.translation
+    }
+}
+
+/// Position of the element at `index` of some list
+pub struct Indexed {
+    pub index: usize,
+    pub translation: Vec3,
+}
+
+impl Located for Indexed {
+    fn translation(&self) -> Vec3 {
+        self.translation
+    }
+}
+
+/// Uniform spatial hash of bodies, used to find all neighbours within a cutoff radius
+pub struct Grid<T = Body> {
+    /// side length of a cell, at least the cutoff radius
+    cell_size: Vec3,
+    /// number of cells along each periodic axis
+    cells_per_axis: IVec3,
+    domain: PeriodicBox,
+    cells: HashMap<IVec3, Vec<T>>,
+}
+
+impl<T: Located> Grid<T> {
+    /// Sorts `bodies` into cells of at least `cutoff` wide, wrapping around
+    /// along the periodic axes of `domain`.
+    pub fn new(bodies: impl Iterator<Item = T>, cutoff: f32, domain: PeriodicBox) -> Self {
+        let cells_per_axis = (domain.size / cutoff).floor().max(Vec3::ONE).as_ivec3();
+        let cell_size = Vec3::select(
+            domain.periodic,
+            domain.size / cells_per_axis.as_vec3(),
+            Vec3::splat(cutoff),
+        );
+
+        let mut grid = Self {
+            cell_size,
+            cells_per_axis,
+            domain,
+            cells: HashMap::default(),
+        };
+        for body in bodies {
+            let cell = grid.cell(body.translation());
+            grid.cells.entry(cell).or_default().push(body);
+        }
+        grid
+    }
+
+    fn cell(&self, translation: Vec3) -> IVec3 {
+        let cell = ((translation - self.domain.min) / self.cell_size)
+            .floor()
+            .as_ivec3();
+        self.wrap(cell)
+    }
+
+    fn wrap(&self, cell: IVec3) -> IVec3 {
+        IVec3::select(
+            self.domain.periodic,
+            cell.rem_euclid(self.cells_per_axis),
+            cell,
+        )
+    }
+
+    /// Calls `f(displacement, body)` for all bodies closer than `cutoff` to `translation`
+    pub fn for_each_neighbour(&self, translation: Vec3, cutoff: f32, mut f: impl FnMut(Vec3, &T)) {
+        let center = self.cell(translation);
+        let cutoff_squared = cutoff * cutoff;
+
+        // with less than three cells along an axis the neighbouring cells overlap
+        let mut visited = Vec::with_capacity(27);
+
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let cell = self.wrap(center + IVec3::new(x, y, z));
+                    if visited.contains(&cell) {
+                        continue;
+                    }
+                    visited.push(cell);
+
+                    let Some(bodies) = self.cells.get(&cell) else {
+                        continue;
+                    };
+                    for body in bodies {
+                        let diff = self.domain.displacement(translation - body.translation());
+                        if diff.length_squared() < cutoff_squared {
+                            f(diff, body);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sums `f(displacement, body)` over all bodies closer than `cutoff` to `translation`
+    pub fn sum_neighbours<S: Default + AddAssign>(
+        &self,
+        translation: Vec3,
+        cutoff: f32,
+        f: impl Fn(Vec3, &T) -> S,
+    ) -> S {
+        let mut sum = S::default();
+        self.for_each_neighbour(translation, cutoff, |diff, body| sum += f(diff, body));
+        sum
+    }
+}
@@ -0,0 +1,266 @@
+use bevy::prelude::*;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Numerical scheme used to advance the particles by a timestep
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub enum Integrator {
+    /// Kick then drift, first order, 1 force evaluation per step
+    #[default]
+    SemiImplicitEuler,
+    /// Position update with the velocity and acceleration, then a velocity update with the
+    /// mean of the accelerations before and after, second order and symplectic
+    VelocityVerlet,
+    /// Half kick, drift, half kick, second order and symplectic, and the same scheme as
+    /// velocity Verlet written as kicks
+    Leapfrog,
+    /// Classical Runge-Kutta, fourth order but not symplectic, 4 force evaluations per step
+    Rk4,
+    /// Boris pusher, a half kick by the electric force, a rotation by the magnetic
+    /// field and another half kick, then a drift. Second order with momenta
+    /// staggered half a step, 1 force evaluation per step, and stable for
+    /// gyration in strong magnetic fields
+    Boris,
+    /// Kick-drift-kick leapfrog with hierarchical block timesteps, where every particle takes
+    /// steps of `dt / 2^level` based on the magnitude of its acceleration
+    Adaptive {
+        /// deepest level a particle can be sub-stepped to
+        max_level: u32,
+        /// accuracy parameter, a particle takes steps of about `eta * sqrt(1 / |a|)`
+        eta: f32,
+    },
+}
+
+/// Force on a particle, split into a part that is independent of its velocity
+/// and a magnetic part
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Force {
+    /// velocity independent force
+    pub force: Vec3,
+    /// magnetic field times the charge of the particle, giving the force `v × qB`
+    pub magnetic: Vec3,
+}
+
+impl Force {
+    pub fn total(&self, velocity: Vec3) -> Vec3 {
+        self.force + velocity.cross(self.magnetic)
+    }
+}
+
+impl Integrator {
+    /// Advances `translations` and `momenta` by `dt`, where `velocity` gives the
+    /// velocity of the particle at an index given its momentum, and `force` gives
+    /// the forces on the particles at the given indices when all particles are
+    /// placed at the given translations and move at the given velocities.
+    ///
+    /// `forces` holds the forces on the particles at the start of the step if they
+    /// are known, and is replaced by the forces at the end of the step if the scheme
+    /// evaluates them there, so the next step can start from them. This makes
+    /// velocity Verlet and leapfrog take 1 force evaluation per step. `levels`
+    /// likewise holds the levels of the adaptive integrator that belong to them.
+    #[allow(clippy::too_many_arguments)]
+    pub fn step(
+        self,
+        translations: &mut [Vec3],
+        momenta: &mut [Vec3],
+        dt: f32,
+        velocity: impl Fn(usize, Vec3) -> Vec3 + Sync,
+        force: impl Fn(&[Vec3], &[Vec3], &[usize]) -> Vec<Force>,
+        forces: &mut Option<Vec<Force>>,
+        levels: &mut Option<Vec<u32>>,
+    ) {
+        let all = (0..translations.len()).collect::<Vec<_>>();
+        let velocities = |momenta: &[Vec3]| -> Vec<Vec3> {
+            momenta
+                .par_iter()
+                .enumerate()
+                .map(|(i, &p)| velocity(i, p))
+                .collect()
+        };
+        let totals = |forces: &[Force], velocities: &[Vec3]| -> Vec<Vec3> {
+            forces
+                .par_iter()
+                .zip(velocities.par_iter())
+                .map(|(force, &v)| force.total(v))
+                .collect()
+        };
+        // total forces on all particles
+        let force_all = |translations: &[Vec3], momenta: &[Vec3]| -> Vec<Vec3> {
+            let velocities = velocities(momenta);
+            totals(&force(translations, &velocities, &all), &velocities)
+        };
+        // forces at the start of the step, evaluated only if they are not known
+        let start = forces
+            .take()
+            .filter(|forces| forces.len() == translations.len());
+        let start_levels = levels
+            .take()
+            .filter(|levels| start.is_some() && levels.len() == translations.len());
+        let start_forces = |translations: &[Vec3], momenta: &[Vec3]| -> Vec<Force> {
+            start.unwrap_or_else(|| force(translations, &velocities(momenta), &all))
+        };
+
+        match self {
+            Integrator::SemiImplicitEuler => {
+                let f0 = totals(&start_forces(translations, momenta), &velocities(momenta));
+                kick(momenta, &f0, dt);
+                drift(translations, &velocities(momenta), dt);
+            }
+            Integrator::VelocityVerlet => {
+                // x += v dt + a dt²/2, with the velocity after half a kick
+                let f0 = totals(&start_forces(translations, momenta), &velocities(momenta));
+                let mut half = momenta.to_vec();
+                kick(&mut half, &f0, dt / 2.0);
+                let half_velocities = velocities(&half);
+                drift(translations, &half_velocities, dt);
+                let end = force(translations, &half_velocities, &all);
+                let f1 = totals(&end, &half_velocities);
+                momenta
+                    .par_iter_mut()
+                    .zip(f0.par_iter().zip(f1.par_iter()))
+                    .for_each(|(p, (f0, f1))| *p += (*f0 + *f1) * (0.5 * dt));
+                *forces = Some(end);
+            }
+            Integrator::Leapfrog => {
+                let f0 = totals(&start_forces(translations, momenta), &velocities(momenta));
+                kick(momenta, &f0, dt / 2.0);
+                let half_velocities = velocities(momenta);
+                drift(translations, &half_velocities, dt);
+                let end = force(translations, &half_velocities, &all);
+                kick(momenta, &totals(&end, &half_velocities), dt / 2.0);
+                *forces = Some(end);
+            }
+            Integrator::Rk4 => {
+                let x0 = translations.to_vec();
+                let p0 = momenta.to_vec();
+
+                let offset = |base: &[Vec3], delta: &[Vec3], h: f32| -> Vec<Vec3> {
+                    base.par_iter()
+                        .zip(delta.par_iter())
+                        .map(|(b, d)| *b + *d * h)
+                        .collect()
+                };
+
+                let k1x = velocities(&p0);
+                let k1p = totals(&start_forces(&x0, &p0), &k1x);
+                let p1 = offset(&p0, &k1p, dt / 2.0);
+                let k2x = velocities(&p1);
+                let k2p = force_all(&offset(&x0, &k1x, dt / 2.0), &p1);
+                let p2 = offset(&p0, &k2p, dt / 2.0);
+                let k3x = velocities(&p2);
+                let k3p = force_all(&offset(&x0, &k2x, dt / 2.0), &p2);
+                let p3 = offset(&p0, &k3p, dt);
+                let k4x = velocities(&p3);
+                let k4p = force_all(&offset(&x0, &k3x, dt), &p3);
+
+                let combine = |k1: &Vec3, k2: &Vec3, k3: &Vec3, k4: &Vec3| {
+                    (*k1 + *k2 * 2.0 + *k3 * 2.0 + *k4) * (dt / 6.0)
+                };
+                translations.par_iter_mut().enumerate().for_each(|(i, x)| {
+                    *x = x0[i] + combine(&k1x[i], &k2x[i], &k3x[i], &k4x[i]);
+                });
+                momenta.par_iter_mut().enumerate().for_each(|(i, p)| {
+                    *p = p0[i] + combine(&k1p[i], &k2p[i], &k3p[i], &k4p[i]);
+                });
+            }
+            Integrator::Boris => {
+                let f0 = start_forces(translations, momenta);
+                momenta
+                    .par_iter_mut()
+                    .zip(f0.par_iter())
+                    .enumerate()
+                    .for_each(|(i, (p, f))| {
+                        let minus = *p + f.force * (dt / 2.0);
+                        // p = γmv, so the rotation angle depends on the speed and momentum
+                        let (speed, momentum) = (velocity(i, minus).length(), minus.length());
+                        let t = if momentum > 0.0 {
+                            f.magnetic * (dt / 2.0 * speed / momentum)
+                        } else {
+                            Vec3::ZERO
+                        };
+                        let s = t * (2.0 / (1.0 + t.length_squared()));
+                        let prime = minus + minus.cross(t);
+                        let plus = minus + prime.cross(s);
+                        *p = plus + f.force * (dt / 2.0);
+                    });
+                drift(translations, &velocities(momenta), dt);
+            }
+            Integrator::Adaptive { max_level, eta } => {
+                let ticks = 1u32 << max_level;
+                let tick_dt = dt / ticks as f32;
+                // number of ticks in a step of the given level
+                let stride = |level: u32| 1u32 << (max_level - level);
+                // the acceleration is estimated from the change in velocity a whole
+                // step of the force would cause
+                let level_of = |i: usize, p: Vec3, f: Vec3| {
+                    let a = (velocity(i, p + f * dt) - velocity(i, p)) / dt;
+                    let step = eta / a.length().sqrt();
+                    ((dt / step).log2().ceil().max(0.0) as u32).min(max_level)
+                };
+
+                // forces of the last evaluation, and the total forces of the
+                // opening kicks that follow from them
+                let mut end = start_forces(translations, momenta);
+                let mut kicks = totals(&end, &velocities(momenta));
+                let mut step_levels = start_levels.unwrap_or_else(|| {
+                    (0..translations.len())
+                        .map(|i| level_of(i, momenta[i], kicks[i]))
+                        .collect()
+                });
+
+                for tick in 0..ticks {
+                    // opening half kick of the particles starting a step
+                    for (i, p) in momenta.iter_mut().enumerate() {
+                        let stride = stride(step_levels[i]);
+                        if tick % stride == 0 {
+                            *p += kicks[i] * (tick_dt * stride as f32 / 2.0);
+                        }
+                    }
+
+                    drift(translations, &velocities(momenta), tick_dt);
+
+                    // closing half kick of the particles ending a step
+                    let active = (0..translations.len())
+                        .filter(|&i| (tick + 1) % stride(step_levels[i]) == 0)
+                        .collect::<Vec<_>>();
+                    if active.is_empty() {
+                        continue;
+                    }
+                    let velocities = velocities(momenta);
+                    let active_forces = force(translations, &velocities, &active);
+                    for (&i, raw) in active.iter().zip(active_forces) {
+                        let f = raw.total(velocities[i]);
+                        momenta[i] += f * (tick_dt * stride(step_levels[i]) as f32 / 2.0);
+                        end[i] = raw;
+                        kicks[i] = f;
+
+                        // the next step has to start at this tick, so it may need to be finer
+                        let mut level = level_of(i, momenta[i], f);
+                        while (tick + 1) % stride(level) != 0 {
+                            level += 1;
+                        }
+                        step_levels[i] = level;
+                    }
+                }
+                // every particle ends a step at the last tick, so these are the
+                // forces and levels at the end of the step
+                *forces = Some(end);
+                *levels = Some(step_levels);
+            }
+        }
+    }
+}
+
+fn kick(momenta: &mut [Vec3], forces: &[Vec3], dt: f32) {
+    momenta
+        .par_iter_mut()
+        .zip(forces.par_iter())
+        .for_each(|(p, f)| *p += *f * dt);
+}
+
+fn drift(translations: &mut [Vec3], velocities: &[Vec3], dt: f32) {
+    translations
+        .par_iter_mut()
+        .zip(velocities.par_iter())
+        .for_each(|(x, v)| *x += *v * dt);
+}
@@ -1,58 +1,284 @@
-use bevy::prelude::*;
-use particle::{ParticleBundle, ParticlePlugin, Velocity};
-use rand::Rng;
+use std::time::Instant;
 
+use annihilation::AnnihilationStats;
+use bevy::{
+    app::{AppExit, PluginsState},
+    ecs::system::RunSystemOnce,
+    log::LogPlugin,
+    prelude::*,
+    time::TimeUpdateStrategy,
+};
+use bound::{BoundKind, BoundStates};
+use boundary::Boundary;
+use checkpoint::{save_checkpoint, CheckpointPlugin, Checkpointing};
+use clap::Parser;
+use cli::Cli;
+use collision::CollisionStats;
+use diagnostics::{diagnostics_update, Diagnostics, DiagnosticsSettings};
+use output::OutputPlugin;
+use particle::{
+    Dimensions, Dynamics, Particle, ParticleBundle, ParticlePlugin, SimulationClock, SpeedOfLight,
+    Timestep,
+};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use scenario::{Region, Scenario, ScenarioError};
+use species::SpeciesRegistry;
+use strong::ColorCharge;
+use trajectory::TrajectoryPlugin;
+use visualisation::VisualisationPlugin;
+
+mod annihilation;
+mod bound;
+mod boundary;
+mod checkpoint;
+mod cli;
+mod collision;
+mod diagnostics;
+mod ewald;
+mod field;
+mod force;
+mod grid;
+mod integrator;
+mod octree;
+mod output;
 mod particle;
+mod scenario;
+mod species;
+mod strong;
+mod trajectory;
+mod visualisation;
 
 pub const SIZE: Vec3 = Vec3::splat(400.0);
 
-const NUM_ELECTRONS: u32 = 1000;
-const NUM_UP_QUARKS: u32 = 1000;
-const NUM_DOWN_QUARKS: u32 = 1000;
+/// Seed of [`SimulationRng`]
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct Seed(pub u64);
+
+/// Random number generator that all randomness of the simulation goes through,
+/// so that runs with the same [`Seed`] are identical
+#[derive(Resource, Clone, Debug, Deref, DerefMut)]
+pub struct SimulationRng(pub ChaCha8Rng);
+
+/// Number of integration steps after which the app exits
+#[derive(Resource, Clone, Copy, Debug)]
+struct MaxSteps(u64);
 
 struct SimulationPlugin;
 
 impl Plugin for SimulationPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(ParticlePlugin).add_systems(Startup, setup);
+        app.add_plugins((
+            ParticlePlugin,
+            OutputPlugin,
+            TrajectoryPlugin,
+            CheckpointPlugin,
+        ))
+        .add_systems(Startup, (rng_setup, setup).chain());
+    }
+}
+
+fn rng_setup(mut commands: Commands, seed: Res<Seed>, rng: Option<Res<SimulationRng>>) {
+    info!("seed {}", seed.0);
+    // a restarted simulation continues with the generator of its checkpoint
+    if rng.is_none() {
+        commands.insert_resource(SimulationRng(ChaCha8Rng::seed_from_u64(seed.0)));
     }
 }
 
-fn random_pos(min: Vec3, max: Vec3) -> Vec3 {
-    let mut rng = rand::thread_rng();
-    let x = rng.gen_range(min.x..max.x);
-    let y = rng.gen_range(min.y..max.y);
-    let z = rng.gen_range(min.z..max.z);
-    Vec3::new(x, y, z)
+#[allow(clippy::too_many_arguments)]
+fn setup(
+    mut commands: Commands,
+    scenario: Res<Scenario>,
+    registry: Res<SpeciesRegistry>,
+    mut rng: ResMut<SimulationRng>,
+    dynamics: Res<Dynamics>,
+    c: Res<SpeedOfLight>,
+    boundary: Res<Boundary>,
+    dimensions: Res<Dimensions>,
+) {
+    let flat = *dimensions == Dimensions::Two;
+    let whole_box = Region::Box {
+        min: -boundary.size,
+        max: boundary.size,
+    };
+
+    let find = |name: &str| {
+        registry
+            .find(name)
+            .expect("the species of the scenario should be validated")
+    };
+
+    let mut spawn = |bundle: ParticleBundle, color: Option<ColorCharge>| {
+        let mut entity = commands.spawn(bundle);
+        if let Some(color) = color {
+            entity.insert(color);
+        }
+    };
+
+    for distribution in &scenario.distributions {
+        let species = find(&distribution.species);
+        let representation = registry.get(species).color_charge;
+        let region = distribution.region.unwrap_or(whole_box);
+        for n in 0..distribution.count as usize {
+            let translation = region.sample(&mut rng.0, flat);
+            let velocity = distribution.velocity.sample(&mut rng.0, flat);
+            let bundle = ParticleBundle::new(
+                &registry,
+                *dynamics,
+                *c,
+                species,
+                Transform::from_translation(translation),
+                dynamics.momentum(*c, registry.get(species).mass, velocity),
+            );
+            spawn(bundle, representation.nth(n));
+        }
+    }
+
+    // number of particles of each species so far, so that the colours of the
+    // quarks cycle within their species as for distributions
+    let mut counts = vec![0; registry.iter().count()];
+    for spec in &scenario.particles {
+        let species = find(&spec.species);
+        let definition = registry.get(species);
+        let particle = Particle {
+            charge: spec.charge.unwrap_or(definition.charge),
+            mass: spec.mass.unwrap_or(definition.mass),
+            ..definition.particle()
+        };
+        let bundle = ParticleBundle::custom(
+            *dynamics,
+            *c,
+            species,
+            particle,
+            spec.radius.unwrap_or(definition.radius),
+            Transform::from_translation(spec.position),
+            dynamics.momentum(*c, particle.mass, spec.velocity),
+        );
+        let representation = registry.get(species).color_charge;
+        spawn(
+            bundle,
+            spec.color_charge.or(representation.nth(counts[species.0])),
+        );
+        counts[species.0] += 1;
+    }
+}
+
+fn exit_update(
+    clock: Res<SimulationClock>,
+    max_steps: Res<MaxSteps>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if clock.steps >= max_steps.0 {
+        exit.send(AppExit);
+    }
 }
 
-fn setup(mut commands: Commands) {
-    commands.spawn_batch((0..NUM_ELECTRONS).map(|_| {
-        ParticleBundle::electron(
-            Transform::from_translation(random_pos(-SIZE, SIZE)),
-            Velocity::default(),
-        )
-    }));
-    commands.spawn_batch((0..NUM_UP_QUARKS).map(|_| {
-        ParticleBundle::up_quark(
-            Transform::from_translation(random_pos(-SIZE, SIZE)),
-            Velocity::default(),
-        )
-    }));
-    commands.spawn_batch((0..NUM_DOWN_QUARKS).map(|_| {
-        ParticleBundle::down_quark(
-            Transform::from_translation(random_pos(-SIZE, SIZE)),
-            Velocity::default(),
-        )
-    }));
-
-    commands.spawn(Camera2dBundle::default());
+/// Runs at least `steps` integration steps as fast as possible and returns
+fn run_headless(mut app: App, steps: u64) {
+    while app.plugins_state() == PluginsState::Adding {
+        bevy::tasks::tick_global_task_pools_on_main_thread();
+    }
+    app.finish();
+    app.cleanup();
+
+    // every update advances the time by exactly one fixed timestep
+    let duration = app.world.resource::<Timestep>().duration();
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(duration));
+
+    let start = Instant::now();
+    while app.world.resource::<SimulationClock>().steps < steps {
+        app.update();
+    }
+    if let Some(path) = app.world.resource::<Checkpointing>().path.clone() {
+        save_checkpoint(&mut app.world, &path);
+    }
+    let elapsed = start.elapsed();
+    // the summary shows the final state, unless the diagnostics are disabled
+    let steps = app.world.resource::<SimulationClock>().steps;
+    if app.world.resource::<DiagnosticsSettings>().enabled
+        && app.world.resource::<Diagnostics>().step != Some(steps)
+    {
+        app.world.run_system_once(diagnostics_update);
+    }
+
+    let clock = app.world.resource::<SimulationClock>();
+    info!(
+        "simulated {} steps, {} s, in {elapsed:?}",
+        clock.steps, clock.elapsed
+    );
+    let diagnostics = app.world.resource::<Diagnostics>();
+    if let Some(step) = diagnostics.step {
+        info!(
+            "{} particles at step {step}, energy {:.6e} eV ({:.6e} kinetic, {:.6e} Coulomb, {:.6e} strong, {:.6e} field), relative drift {:.3e}",
+            diagnostics.particles,
+            diagnostics.energy(),
+            diagnostics.kinetic_energy,
+            diagnostics.potential_energy,
+            diagnostics.strong_energy,
+            diagnostics.field_energy,
+            diagnostics.drift()
+        );
+        info!(
+            "momentum {}, angular momentum {}, centre of mass {}",
+            diagnostics.momentum, diagnostics.angular_momentum, diagnostics.center_of_mass
+        );
+    }
+    let annihilations = app.world.resource::<AnnihilationStats>();
+    if annihilations.pairs > 0 {
+        info!(
+            "annihilated {} pairs releasing {} eV, {} per s",
+            annihilations.pairs,
+            annihilations.energy,
+            annihilations.rate(clock)
+        );
+    }
+    let collisions = app.world.resource::<CollisionStats>();
+    if collisions.bounces > 0 || collisions.merges > 0 {
+        info!(
+            "{} bounces and {} merges",
+            collisions.bounces, collisions.merges
+        );
+    }
+    let bound_states = app.world.resource::<BoundStates>();
+    for kind in BoundKind::ALL {
+        let (count, particles) = bound_states.count(kind);
+        if count > 0 {
+            info!("{count} bound states of kind {kind}, with {particles} particles");
+        }
+    }
 }
 
 fn main() {
+    let cli = Cli::parse();
+
     let mut app = App::new();
-    app.add_plugins(DefaultPlugins)
-        .add_plugins(SimulationPlugin)
-        .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)));
+    app.add_plugins(SimulationPlugin);
+    if let Err(error) = cli.insert_resources(&mut app) {
+        let path = match (&error, &cli.initial_frame) {
+            (ScenarioError::Argument { .. }, _) => None,
+            (ScenarioError::Frame { .. }, Some(frame)) => Some(frame.clone()),
+            (ScenarioError::Checkpoint(_), _) => cli.restart.clone(),
+            _ => cli.scenario.clone(),
+        };
+        match path {
+            Some(path) => eprintln!("error: {}: {error}", path.display()),
+            None => eprintln!("error: {error}"),
+        }
+        std::process::exit(1);
+    }
+
+    if cli.headless {
+        let steps = cli.steps.expect("--headless requires --steps");
+        app.add_plugins((MinimalPlugins, LogPlugin::default()))
+            .set_runner(move |app| run_headless(app, steps));
+    } else {
+        app.add_plugins((DefaultPlugins, VisualisationPlugin))
+            .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)));
+        if let Some(steps) = cli.steps {
+            app.insert_resource(MaxSteps(steps))
+                .add_systems(Last, exit_update);
+        }
+    }
     app.run();
 }
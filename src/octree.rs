@@ -0,0 +1,289 @@
+use std::ops::{AddAssign, Range};
+
+use bevy::prelude::*;
+
+use crate::force::{pair_softening, Body, ForceLaw, PeriodicBox};
+
+/// Maximum number of bodies in a leaf before it is subdivided
+const LEAF_CAPACITY: usize = 8;
+/// Depth at which subdivision stops, so coincident bodies can not recurse forever
+const MAX_DEPTH: u32 = 24;
+
+struct Node {
+    /// half of the side length of the cube covered by the node
+    half_size: f32,
+    /// sum of the charges of all bodies in the node
+    charge: f32,
+    /// centre of the bodies in the node, weighted by the magnitude of their
+    /// charge, which the moments are taken around
+    center: Vec3,
+    /// dipole moment Σ q d, with d the offset of a body from the centre
+    dipole: Vec3,
+    /// traceless quadrupole moment Σ q (3 d dᵀ - |d|² I)
+    quadrupole: Mat3,
+    /// largest softening length of the bodies in the node
+    softening: Option<f32>,
+    /// indices of the children in `Octree::nodes`, empty for leaves
+    children: Range<u32>,
+    /// indices of the bodies in `Octree::bodies` contained in the node
+    bodies: Range<u32>,
+}
+
+/// Barnes-Hut tree that approximates the field of distant groups of charges by
+/// their monopole, dipole and quadrupole moments.
+///
+/// The higher moments matter as the particles are nearly neutral overall, so
+/// the total charge of a distant group is often close to zero.
+pub struct Octree {
+    nodes: Vec<Node>,
+    bodies: Vec<Body>,
+}
+
+impl Octree {
+    pub fn new(mut bodies: Vec<Body>) -> Self {
+        let (min, max) = bodies.iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), body| (min.min(body.translation), max.max(body.translation)),
+        );
+        let center = (min + max) / 2.0;
+        let half_size = ((max - min).max_element() / 2.0).max(f32::EPSILON);
+
+        let mut nodes = Vec::with_capacity(bodies.len() / LEAF_CAPACITY * 2 + 1);
+        nodes.push(Node::empty());
+        let len = bodies.len() as u32;
+        Self::build(&mut nodes, &mut bodies, 0, 0..len, center, half_size, 0);
+
+        Self { nodes, bodies }
+    }
+
+    fn build(
+        nodes: &mut Vec<Node>,
+        bodies: &mut [Body],
+        index: usize,
+        range: Range<u32>,
+        center: Vec3,
+        half_size: f32,
+        depth: u32,
+    ) {
+        let slice = &mut bodies[range.start as usize..range.end as usize];
+
+        let (charge, weighted, weight, softening) = slice.iter().fold(
+            (0.0, Vec3::ZERO, 0.0, None),
+            |(charge, weighted, weight, softening), body| {
+                let w = body.charge.abs();
+                (
+                    charge + body.charge,
+                    weighted + body.translation * w,
+                    weight + w,
+                    pair_softening(softening, body.softening),
+                )
+            },
+        );
+        // the moments are taken around the centre of charge, not of the cube
+        let expansion = if weight > 0.0 {
+            weighted / weight
+        } else {
+            center
+        };
+        let (dipole, quadrupole) =
+            slice
+                .iter()
+                .fold((Vec3::ZERO, Mat3::ZERO), |(dipole, quadrupole), body| {
+                    let d = body.translation - expansion;
+                    let outer = Mat3::from_cols(d * d.x, d * d.y, d * d.z) * 3.0
+                        - Mat3::from_diagonal(Vec3::splat(d.length_squared()));
+                    (dipole + d * body.charge, quadrupole + outer * body.charge)
+                });
+        nodes[index] = Node {
+            half_size,
+            charge,
+            center: expansion,
+            dipole,
+            quadrupole,
+            softening,
+            children: 0..0,
+            bodies: range.clone(),
+        };
+
+        if slice.len() <= LEAF_CAPACITY || depth >= MAX_DEPTH {
+            return;
+        }
+
+        let octant = |body: &Body| {
+            let t = body.translation;
+            (t.x >= center.x) as usize
+                | ((t.y >= center.y) as usize) << 1
+                | ((t.z >= center.z) as usize) << 2
+        };
+        slice.sort_unstable_by_key(octant);
+
+        // split the sorted bodies into the non-empty octants
+        let mut octants = Vec::with_capacity(8);
+        let mut start = 0;
+        while start < slice.len() {
+            let o = octant(&slice[start]);
+            let len = slice[start..].partition_point(|b| octant(b) == o);
+            octants.push((o, start as u32..(start + len) as u32));
+            start += len;
+        }
+
+        let first = nodes.len() as u32;
+        nodes.extend((0..octants.len()).map(|_| Node::empty()));
+        nodes[index].children = first..first + octants.len() as u32;
+
+        let child_half = half_size / 2.0;
+        for (i, (o, sub)) in octants.into_iter().enumerate() {
+            let offset = Vec3::new(
+                if o & 1 != 0 { child_half } else { -child_half },
+                if o & 2 != 0 { child_half } else { -child_half },
+                if o & 4 != 0 { child_half } else { -child_half },
+            );
+            Self::build(
+                nodes,
+                bodies,
+                first as usize + i,
+                range.start + sub.start..range.start + sub.end,
+                center + offset,
+                child_half,
+                depth + 1,
+            );
+        }
+    }
+
+    /// Partially calculated force on a unit charge at `translation` with
+    /// `softening`, see [`ForceLaw::semi_force`].
+    ///
+    /// A node is approximated by its moments when its size divided by its
+    /// distance is smaller than the opening angle `theta`. Only the monopole
+    /// is softened, which is accurate as long as the node is much further away
+    /// than the softening length. Distances follow the minimum image convention
+    /// of `domain`, which is only accurate for nodes much smaller than the box.
+    pub fn semi_force(
+        &self,
+        law: &ForceLaw,
+        domain: &PeriodicBox,
+        translation: Vec3,
+        softening: Option<f32>,
+        theta: f32,
+    ) -> Vec3 {
+        self.walk(
+            domain,
+            translation,
+            theta,
+            |node, diff| {
+                law.semi_force(diff, node.charge, pair_softening(softening, node.softening))
+                    + node.multipole_field(diff)
+            },
+            |body, diff| {
+                law.semi_force(diff, body.charge, pair_softening(softening, body.softening))
+            },
+        )
+    }
+
+    /// Potential of a unit charge at `translation` with `softening`, see
+    /// [`ForceLaw::potential`], with the same approximations as
+    /// [`semi_force`](Self::semi_force).
+    ///
+    /// A body at `translation` contributes its own potential at distance 0.
+    pub fn potential(
+        &self,
+        law: &ForceLaw,
+        domain: &PeriodicBox,
+        translation: Vec3,
+        softening: Option<f32>,
+        theta: f32,
+    ) -> f32 {
+        self.walk(
+            domain,
+            translation,
+            theta,
+            |node, diff| {
+                law.potential(
+                    diff.length(),
+                    node.charge,
+                    pair_softening(softening, node.softening),
+                ) + node.multipole_potential(diff)
+            },
+            |body, diff| {
+                law.potential(
+                    diff.length(),
+                    body.charge,
+                    pair_softening(softening, body.softening),
+                )
+            },
+        )
+    }
+
+    /// Sums `node_term` over the nodes that are approximated by their moments
+    /// and `body_term` over the bodies of the leaves that are opened, given the
+    /// displacement of `translation` from the centre of the node or the body
+    fn walk<S: Default + AddAssign>(
+        &self,
+        domain: &PeriodicBox,
+        translation: Vec3,
+        theta: f32,
+        node_term: impl Fn(&Node, Vec3) -> S,
+        body_term: impl Fn(&Body, Vec3) -> S,
+    ) -> S {
+        let theta_squared = theta * theta;
+        let mut sum = S::default();
+        let mut stack = vec![0u32];
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            let diff = domain.displacement(translation - node.center);
+            let size = node.half_size * 2.0;
+
+            if size * size < theta_squared * diff.length_squared() {
+                sum += node_term(node, diff);
+            } else if node.children.is_empty() {
+                for body in &self.bodies[node.bodies.start as usize..node.bodies.end as usize] {
+                    sum += body_term(body, domain.displacement(translation - body.translation));
+                }
+            } else {
+                stack.extend(node.children.clone());
+            }
+        }
+        sum
+    }
+}
+
+impl Node {
+    fn empty() -> Self {
+        Self {
+            half_size: 0.0,
+            charge: 0.0,
+            center: Vec3::ZERO,
+            dipole: Vec3::ZERO,
+            quadrupole: Mat3::ZERO,
+            softening: None,
+            children: 0..0,
+            bodies: 0..0,
+        }
+    }
+
+    /// Field of the dipole and quadrupole moments at a displacement of `diff`
+    /// from the centre
+    fn multipole_field(&self, diff: Vec3) -> Vec3 {
+        let dist_squared = diff.length_squared();
+        let inv_squared = 1.0 / dist_squared;
+        let inv_cubed = inv_squared / dist_squared.sqrt();
+        let inv_fifth = inv_cubed * inv_squared;
+
+        let dipole = diff * (3.0 * self.dipole.dot(diff) * inv_fifth) - self.dipole * inv_cubed;
+        let q_diff = self.quadrupole * diff;
+        let quadrupole =
+            diff * (2.5 * diff.dot(q_diff) * inv_fifth * inv_squared) - q_diff * inv_fifth;
+        dipole + quadrupole
+    }
+
+    /// Potential of the dipole and quadrupole moments at a displacement of
+    /// `diff` from the centre
+    fn multipole_potential(&self, diff: Vec3) -> f32 {
+        let dist_squared = diff.length_squared();
+        let inv_cubed = 1.0 / (dist_squared * dist_squared.sqrt());
+        let inv_fifth = inv_cubed / dist_squared;
+
+        self.dipole.dot(diff) * inv_cubed + 0.5 * diff.dot(self.quadrupole * diff) * inv_fifth
+    }
+}
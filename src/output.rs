@@ -0,0 +1,290 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    checkpoint::Restarted,
+    diagnostics::{diagnostics_update, Diagnostics},
+    particle::{Dimensions, PhysicsStep, SimulationClock},
+    species::{SpeciesId, SpeciesRegistry},
+};
+
+/// How the observables are written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum OutputFormat {
+    /// One CSV file with a header and a row per sample
+    #[default]
+    Csv,
+    /// A directory with a raw little-endian f64 file per column, named after
+    /// the column, and `columns.txt` listing the columns in order
+    Columnar,
+}
+
+/// Settings of the time series of observables
+#[derive(Resource, Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Output {
+    /// file or directory the observables are written to, nothing is written if not set
+    pub path: Option<PathBuf>,
+    #[serde(default)]
+    pub format: OutputFormat,
+    /// number of integration steps between samples
+    pub interval: u64,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self {
+            path: None,
+            format: OutputFormat::Csv,
+            interval: 1,
+        }
+    }
+}
+
+/// Writes the [`Diagnostics`] and the number of particles of every species to
+/// a file, in windowed and headless mode alike, for the initial state and every
+/// `interval` steps after.
+///
+/// A simulation restarted from a checkpoint continues the existing file, without
+/// the samples the original run wrote after the checkpoint.
+pub struct OutputPlugin;
+
+impl Plugin for OutputPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Output>()
+            .add_systems(Startup, output_setup)
+            .add_systems(
+                PostStartup,
+                output_update
+                    .after(diagnostics_update)
+                    .run_if(not(resource_exists::<Restarted>)),
+            )
+            .add_systems(PhysicsStep, output_update.after(diagnostics_update));
+    }
+}
+
+enum Sink {
+    Csv(BufWriter<File>),
+    Columnar(Vec<BufWriter<File>>),
+}
+
+/// Open output files, removed when writing fails
+#[derive(Resource)]
+struct OutputWriter(Sink);
+
+impl OutputWriter {
+    fn create(output: &Output, path: &Path, columns: &[String]) -> io::Result<Self> {
+        let sink = match output.format {
+            OutputFormat::Csv => {
+                let mut file = BufWriter::new(File::create(path)?);
+                writeln!(file, "{}", columns.join(","))?;
+                file.flush()?;
+                Sink::Csv(file)
+            }
+            OutputFormat::Columnar => {
+                fs::create_dir_all(path)?;
+                fs::write(path.join("columns.txt"), columns.join("\n") + "\n")?;
+                let files = columns
+                    .iter()
+                    .map(|column| Ok(BufWriter::new(File::create(path.join(column))?)))
+                    .collect::<io::Result<_>>()?;
+                Sink::Columnar(files)
+            }
+        };
+        Ok(Self(sink))
+    }
+
+    /// Opens the files written before the checkpoint at `step`, dropping the
+    /// samples after it
+    fn resume(output: &Output, path: &Path, columns: &[String], step: u64) -> io::Result<Self> {
+        let mismatch = || io::Error::other("the columns do not match the simulation");
+        let sink = match output.format {
+            OutputFormat::Csv => {
+                let text = fs::read_to_string(path)?;
+                let mut lines = text.split_inclusive('\n');
+                if lines.next().map(str::trim_end) != Some(&columns.join(",")) {
+                    return Err(mismatch());
+                }
+                let len = text.len()
+                    - lines
+                        .skip_while(|line| {
+                            let sample = line.split(',').next().and_then(|s| s.parse::<f64>().ok());
+                            sample.is_some_and(|sample| sample <= step as f64)
+                        })
+                        .map(str::len)
+                        .sum::<usize>();
+                Sink::Csv(BufWriter::new(truncate(path, len as u64)?))
+            }
+            OutputFormat::Columnar => {
+                if fs::read_to_string(path.join("columns.txt"))? != columns.join("\n") + "\n" {
+                    return Err(mismatch());
+                }
+                let samples = fs::read(path.join("step"))?
+                    .chunks_exact(8)
+                    .take_while(|bytes| {
+                        f64::from_le_bytes((*bytes).try_into().unwrap()) <= step as f64
+                    })
+                    .count();
+                let files = columns
+                    .iter()
+                    .map(|column| {
+                        Ok(BufWriter::new(truncate(
+                            &path.join(column),
+                            samples as u64 * 8,
+                        )?))
+                    })
+                    .collect::<io::Result<_>>()?;
+                Sink::Columnar(files)
+            }
+        };
+        Ok(Self(sink))
+    }
+
+    fn write(&mut self, row: &[f64]) -> io::Result<()> {
+        match &mut self.0 {
+            Sink::Csv(file) => {
+                let row = row.iter().map(f64::to_string).collect::<Vec<_>>();
+                writeln!(file, "{}", row.join(","))?;
+                file.flush()
+            }
+            Sink::Columnar(files) => {
+                for (file, value) in files.iter_mut().zip(row) {
+                    file.write_all(&value.to_le_bytes())?;
+                    file.flush()?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Names of the columns, followed by the particle count of every species
+pub(crate) const COLUMNS: [&str; 19] = [
+    "step",
+    "time",
+    "particles",
+    "kinetic_energy",
+    "coulomb_energy",
+    "strong_energy",
+    "field_energy",
+    "total_energy",
+    "energy_drift",
+    "temperature",
+    "momentum_x",
+    "momentum_y",
+    "momentum_z",
+    "angular_momentum_x",
+    "angular_momentum_y",
+    "angular_momentum_z",
+    "center_of_mass_x",
+    "center_of_mass_y",
+    "center_of_mass_z",
+];
+
+/// Opens `path` for writing after its first `len` bytes
+fn truncate(path: &Path, len: u64) -> io::Result<File> {
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(len)?;
+    file.seek(SeekFrom::End(0))?;
+    Ok(file)
+}
+
+fn output_setup(
+    mut commands: Commands,
+    output: Res<Output>,
+    registry: Res<SpeciesRegistry>,
+    restarted: Option<Res<Restarted>>,
+) {
+    let Some(path) = &output.path else {
+        return;
+    };
+    let columns = COLUMNS
+        .iter()
+        .map(|column| column.to_string())
+        .chain(registry.iter().map(|(_, species)| species.name.clone()))
+        .collect::<Vec<_>>();
+    let writer = match restarted {
+        Some(restarted) if path.exists() => {
+            OutputWriter::resume(&output, path, &columns, restarted.0)
+        }
+        _ => OutputWriter::create(&output, path, &columns),
+    };
+    match writer {
+        Ok(writer) => commands.insert_resource(writer),
+        Err(error) => error!("could not open {}: {error}", path.display()),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn output_update(
+    mut commands: Commands,
+    query: Query<&SpeciesId>,
+    writer: Option<ResMut<OutputWriter>>,
+    output: Res<Output>,
+    diagnostics: Res<Diagnostics>,
+    clock: Res<SimulationClock>,
+    registry: Res<SpeciesRegistry>,
+    dimensions: Res<Dimensions>,
+) {
+    let Some(mut writer) = writer else {
+        return;
+    };
+    if !clock.steps.is_multiple_of(output.interval.max(1)) {
+        return;
+    }
+
+    let mut counts = vec![0.0; registry.iter().count()];
+    for species in query.iter() {
+        counts[species.0] += 1.0;
+    }
+    // from equipartition, the kinetic energy per particle and degree of freedom is T/2
+    let degrees_of_freedom = match *dimensions {
+        Dimensions::Two => 2.0,
+        Dimensions::Three => 3.0,
+    };
+    let temperature = if diagnostics.particles > 0 {
+        2.0 * diagnostics.kinetic_energy / (degrees_of_freedom * diagnostics.particles as f64)
+    } else {
+        0.0
+    };
+    let (p, l, com) = (
+        diagnostics.momentum.as_dvec3(),
+        diagnostics.angular_momentum.as_dvec3(),
+        diagnostics.center_of_mass.as_dvec3(),
+    );
+    let row = [
+        clock.steps as f64,
+        clock.elapsed,
+        diagnostics.particles as f64,
+        diagnostics.kinetic_energy,
+        diagnostics.potential_energy,
+        diagnostics.strong_energy,
+        diagnostics.field_energy,
+        diagnostics.energy(),
+        diagnostics.drift(),
+        temperature,
+        p.x,
+        p.y,
+        p.z,
+        l.x,
+        l.y,
+        l.z,
+        com.x,
+        com.y,
+        com.z,
+    ]
+    .into_iter()
+    .chain(counts)
+    .collect::<Vec<_>>();
+
+    if let Err(error) = writer.write(&row) {
+        error!("could not write the output, stopping it: {error}");
+        commands.remove_resource::<OutputWriter>();
+    }
+}
@@ -1,252 +1,449 @@
-use bevy::{
-    prelude::*,
-    render::mesh::CircleMeshBuilder,
-    sprite::{MaterialMesh2dBundle, Mesh2dHandle},
-};
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::time::Duration;
+
+use bevy::{ecs::schedule::ScheduleLabel, prelude::*};
+use serde::{Deserialize, Serialize};
 
-use crate::SIZE;
+use crate::{
+    annihilation::{annihilation_update, Annihilation, AnnihilationStats},
+    bound::{bound_state_update, BoundStateDetection, BoundStates},
+    boundary::{boundary_update, Boundary},
+    collision::{collision_update, CollisionStats, Collisions},
+    diagnostics::{
+        diagnostics_due, diagnostics_update, Diagnostics, DiagnosticsSettings, DriftThreshold,
+    },
+    field::{lorentz_forces, ExternalField, MagneticInteraction},
+    force::{calculate_forces, ForceBackend, ForceLaw},
+    integrator::{Force, Integrator},
+    species::{SpeciesId, SpeciesRegistry},
+    strong::{ColorCharge, StrongForce},
+};
 
 pub struct ParticlePlugin;
 
 impl Plugin for ParticlePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup).add_systems(
-            Update,
-            (
-                update,
-                velocity_update,
-                loop_translation_update,
-                mass_update,
+        app.init_resource::<Annihilation>()
+            .init_resource::<AnnihilationStats>()
+            .init_resource::<Boundary>()
+            .init_resource::<BoundStateDetection>()
+            .init_resource::<BoundStates>()
+            .init_resource::<Collisions>()
+            .init_resource::<CollisionStats>()
+            .init_resource::<Diagnostics>()
+            .init_resource::<DiagnosticsSettings>()
+            .init_resource::<Dimensions>()
+            .init_resource::<DriftThreshold>()
+            .init_resource::<Dynamics>()
+            .init_resource::<ExternalField>()
+            .init_resource::<ForceBackend>()
+            .init_resource::<ForceLaw>()
+            .init_resource::<Integrator>()
+            .init_resource::<MagneticInteraction>()
+            .init_resource::<SpeciesRegistry>()
+            .init_resource::<StrongForce>()
+            .init_resource::<Timestep>()
+            .init_resource::<SimulationClock>()
+            .init_resource::<StepForces>()
+            .init_resource::<SpeedOfLight>()
+            .init_schedule(PhysicsStep)
+            .add_systems(PreUpdate, timestep_update)
+            .add_systems(FixedUpdate, (run_physics_steps, bound_state_update).chain())
+            .add_systems(
+                PhysicsStep,
+                (
+                    update,
+                    flatten_update.run_if(resource_equals(Dimensions::Two)),
+                    boundary_update,
+                    annihilation_update,
+                    collision_update,
+                    mass_update,
+                    clock_update,
+                    diagnostics_update.run_if(diagnostics_due),
+                )
+                    .chain(),
             )
-                .chain(),
-        );
+            .add_systems(PostStartup, diagnostics_update.run_if(diagnostics_due));
     }
 }
 
-fn setup(mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<ColorMaterial>>) {
-    for Visualisation {
-        material,
-        mesh,
-        radius,
-        color,
-    } in Visualisation::ALL
-    {
-        meshes.insert(mesh, CircleMeshBuilder::new(radius, 5).build());
-        materials.insert(material, color.into());
+/// Speed of light in m/s * k_e / e, the limit of the speed of massive particles
+/// with relativistic dynamics and the speed of massless ones.
+///
+/// In the units of the simulation it would be about 1.7e37, far beyond the
+/// precision of the positions and any speed the particles reach, so it is a
+/// parameter instead. The default is well above the typical speeds of the
+/// particles, which stay below a few units per second, so only the fastest show
+/// relativistic effects.
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SpeedOfLight(pub f32);
+
+impl Default for SpeedOfLight {
+    fn default() -> Self {
+        Self(10.0)
     }
 }
 
-#[derive(Bundle)]
-pub struct ParticleBundle {
-    particle: Particle,
-    loop_translation: LoopTranslation,
-    /// Velocity of a particle in m/s * k_e / e
-    velocity: Velocity,
-    /// Mass of a particle in e_v
-    mass: Mass,
-    /// Visualisation of the particle
-    /// And transform of a particle in m * k_e / e (m * Coulomb's constant / elementary charge)
-    material_mesh_2d_bundle: MaterialMesh2dBundle<ColorMaterial>,
+/// Relation between the momentum and the velocity of a particle
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Dynamics {
+    /// Special relativity, p = γmv, so no particle reaches the speed of light
+    #[default]
+    Relativistic,
+    /// Classical mechanics, p = mv, for comparison
+    Newtonian,
 }
 
-impl ParticleBundle {
-    fn new(
-        particle: Particle,
-        visualisation: Visualisation,
-        transform: Transform,
-        velocity: Velocity,
-    ) -> Self {
+impl Dynamics {
+    /// Velocity of a particle with rest `mass` and `momentum`, given the speed
+    /// of light `c`.
+    ///
+    /// Massless particles always move at the speed of light, in the direction of
+    /// their momentum.
+    pub fn velocity(self, c: SpeedOfLight, mass: f32, momentum: Vec3) -> Vec3 {
+        if mass == 0.0 {
+            return momentum.normalize_or_zero() * c.0;
+        }
+        match self {
+            Dynamics::Relativistic => {
+                // v = pc² / E
+                momentum * c.0 / (mass * mass * c.0 * c.0 + momentum.length_squared()).sqrt()
+            }
+            Dynamics::Newtonian => momentum / mass,
+        }
+    }
+
+    /// Momentum of a particle with rest `mass` and `velocity`, which has to be
+    /// slower than light for relativistic dynamics.
+    ///
+    /// The momentum of massless particles equals their velocity.
+    pub fn momentum(self, c: SpeedOfLight, mass: f32, velocity: Vec3) -> Vec3 {
+        if mass == 0.0 {
+            return velocity;
+        }
+        match self {
+            Dynamics::Relativistic => {
+                let beta_squared = velocity.length_squared() / (c.0 * c.0);
+                velocity * mass / (1.0 - beta_squared).sqrt()
+            }
+            Dynamics::Newtonian => velocity * mass,
+        }
+    }
+
+    /// Total energy in electronvolts of a particle with rest `mass` and
+    /// `momentum`, including its rest energy
+    pub fn energy(self, c: SpeedOfLight, mass: f32, momentum: Vec3) -> f32 {
+        let rest_energy = mass * c.0 * c.0;
+        match self {
+            Dynamics::Relativistic => rest_energy.hypot(momentum.length() * c.0),
+            Dynamics::Newtonian if mass == 0.0 => momentum.length() * c.0,
+            Dynamics::Newtonian => rest_energy + momentum.length_squared() / (2.0 * mass),
+        }
+    }
+}
+
+/// Number of spatial dimensions the particles move in
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Dimensions {
+    /// The particles move in the xy-plane, with z pinned to 0
+    #[default]
+    Two,
+    Three,
+}
+
+/// Schedule that advances the physics by [`Timestep::delta`], run
+/// [`Timestep::substeps`] times per [`FixedUpdate`]
+#[derive(ScheduleLabel, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PhysicsStep;
+
+/// Rate at which the physics is simulated, independent of the frame rate
+#[derive(Resource, Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct Timestep {
+    /// simulated seconds per fixed update
+    pub step: f32,
+    /// number of integration steps per fixed update
+    pub substeps: u32,
+}
+
+impl Default for Timestep {
+    fn default() -> Self {
         Self {
-            particle,
-            loop_translation: LoopTranslation,
-            mass: Mass(particle.mass),
-            velocity,
-            material_mesh_2d_bundle: MaterialMesh2dBundle {
-                transform,
-                material: visualisation.material,
-                mesh: Mesh2dHandle(visualisation.mesh),
-                ..default()
-            },
+            step: 1.0 / 60.0,
+            substeps: 1,
         }
     }
+}
 
-    pub fn electron(transform: Transform, velocity: Velocity) -> Self {
-        Self::new(
-            Particle::ELECTRON,
-            Visualisation::ELECTRON,
-            transform,
-            velocity,
-        )
+impl Timestep {
+    /// Duration of a single integration step
+    pub fn delta(&self) -> f32 {
+        self.step / self.substeps as f32
     }
 
-    pub fn up_quark(transform: Transform, velocity: Velocity) -> Self {
-        Self::new(
-            Particle::UP_QUARK,
-            Visualisation::UP_QUARK,
-            transform,
-            velocity,
-        )
+    /// Duration of a fixed update
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f32(self.step)
     }
+}
 
-    pub fn down_quark(transform: Transform, velocity: Velocity) -> Self {
-        Self::new(
-            Particle::DOWN_QUARK,
-            Visualisation::DOWN_QUARK,
-            transform,
-            velocity,
-        )
+fn timestep_update(timestep: Res<Timestep>, mut time: ResMut<Time<Fixed>>) {
+    if timestep.is_changed() {
+        time.set_timestep(timestep.duration());
     }
 }
 
-pub struct Visualisation {
-    material: Handle<ColorMaterial>,
-    mesh: Handle<Mesh>,
-    radius: f32,
-    color: Color,
+/// Progress of the simulation
+#[derive(Resource, Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct SimulationClock {
+    /// number of integration steps taken
+    pub steps: u64,
+    /// simulated time in seconds
+    pub elapsed: f64,
 }
 
-impl Visualisation {
-    pub const ELECTRON: Self = Self {
-        material: Handle::weak_from_u128(13652880569953508365),
-        mesh: Handle::weak_from_u128(6525358019767708978),
-        radius: 1.0, // not realistic
-        color: Color::rgb_linear(0.3, 0.3, 1.0),
-    };
-    pub const UP_QUARK: Self = Self {
-        material: Handle::weak_from_u128(15457461644197111779),
-        mesh: Handle::weak_from_u128(15378691927712692583),
-        radius: 0.5, // not realistic
-        color: Color::rgb_linear(0.8, 0.3, 0.3),
-    };
-    pub const DOWN_QUARK: Self = Self {
-        material: Handle::weak_from_u128(6991950750307369590),
-        mesh: Handle::weak_from_u128(7421317758493431304),
-        radius: 0.5, // not realistic
-        color: Color::rgb_linear(0.3, 0.8, 0.3),
-    };
+/// Forces on the particles at the end of the last step, which the next step
+/// starts from instead of evaluating them again, together with the levels of
+/// the adaptive integrator.
+///
+/// They stay valid while the particles only move through periodic boundaries
+/// between the steps, so systems that move them otherwise clear them, and they
+/// are not used once the particles differ from `entities`.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct StepForces {
+    /// particles in the order of the queries
+    pub entities: Vec<Entity>,
+    pub forces: Option<Vec<Force>>,
+    pub levels: Option<Vec<u32>>,
+}
 
-    pub const ALL: [Self; 3] = [Self::ELECTRON, Self::UP_QUARK, Self::DOWN_QUARK];
+impl StepForces {
+    pub fn clear(&mut self) {
+        self.forces = None;
+        self.levels = None;
+    }
 }
 
-#[derive(Component)]
-pub struct LoopTranslation;
+pub fn clock_update(mut clock: ResMut<SimulationClock>, timestep: Res<Timestep>) {
+    clock.steps += 1;
+    clock.elapsed += timestep.delta() as f64;
+}
 
-fn loop_translation_update(mut query: Query<&mut Transform>) {
-    // does not account for any scaling factor or movement of the camera
-    let dimensions = SIZE.xy();
+fn run_physics_steps(world: &mut World) {
+    for _ in 0..world.resource::<Timestep>().substeps {
+        world.run_schedule(PhysicsStep);
+    }
+}
 
-    for mut transform in query.iter_mut() {
-        let p = &mut transform.translation;
+#[derive(Bundle)]
+pub struct ParticleBundle {
+    particle: Particle,
+    species: SpeciesId,
+    radius: Radius,
+    /// Momentum of a particle in eV/c
+    momentum: Momentum,
+    /// Velocity of a particle in m/s * k_e / e
+    velocity: Velocity,
+    /// Mass of a particle in eV/c²
+    mass: Mass,
+    /// Transform of a particle in m * k_e / e (m * Coulomb's constant / elementary charge)
+    spatial_bundle: SpatialBundle,
+}
 
-        if p.x > dimensions.x {
-            p.x = -dimensions.x;
-        } else if p.x < -dimensions.x {
-            p.x = dimensions.x;
-        }
-        if p.y > dimensions.y {
-            p.y = -dimensions.y;
-        } else if p.y < -dimensions.y {
-            p.y = dimensions.y;
+impl ParticleBundle {
+    pub fn new(
+        registry: &SpeciesRegistry,
+        dynamics: Dynamics,
+        c: SpeedOfLight,
+        species: SpeciesId,
+        transform: Transform,
+        momentum: Vec3,
+    ) -> Self {
+        let definition = registry.get(species);
+        Self::custom(
+            dynamics,
+            c,
+            species,
+            definition.particle(),
+            definition.radius,
+            transform,
+            momentum,
+        )
+    }
+
+    /// Particle of `species` with its own charge, mass and `radius`, like the
+    /// bodies formed by merging collisions
+    pub fn custom(
+        dynamics: Dynamics,
+        c: SpeedOfLight,
+        species: SpeciesId,
+        particle: Particle,
+        radius: f32,
+        transform: Transform,
+        momentum: Vec3,
+    ) -> Self {
+        Self {
+            particle,
+            species,
+            radius: Radius(radius),
+            momentum: Momentum(momentum),
+            velocity: Velocity(dynamics.velocity(c, particle.mass, momentum)),
+            mass: Mass::new(dynamics, c, &particle, momentum),
+            spatial_bundle: SpatialBundle::from_transform(transform),
         }
     }
 }
 
+/// Momentum in eV/c, which the particles are advanced by
 #[derive(Component, Deref, DerefMut, Default)]
-pub struct Velocity(Vec3);
+pub struct Momentum(pub Vec3);
 
-fn velocity_update(mut query: Query<(&mut Transform, &Velocity)>) {
-    query.par_iter_mut().for_each(|(mut transform, velocity)| {
-        transform.translation += velocity.0;
-    })
-}
+/// Velocity following from the [`Momentum`] and [`Dynamics`]
+#[derive(Component, Deref, DerefMut, Default)]
+pub struct Velocity(pub Vec3);
 
+/// Total energy of a particle divided by c², which is the relativistic mass
+/// γm for relativistic dynamics and the rest mass for Newtonian dynamics
 #[derive(Component, Clone, Copy)]
-pub struct Mass(f32);
+pub struct Mass(pub f32);
+
+impl Mass {
+    fn new(dynamics: Dynamics, c: SpeedOfLight, particle: &Particle, momentum: Vec3) -> Self {
+        Self(match dynamics {
+            Dynamics::Relativistic => dynamics.energy(c, particle.mass, momentum) / (c.0 * c.0),
+            Dynamics::Newtonian => particle.mass,
+        })
+    }
+}
+
+/// Radius of a particle in m * k_e / e, within which other particles collide with it
+#[derive(Component, Clone, Copy, Deref, DerefMut)]
+pub struct Radius(pub f32);
 
-fn mass_update(mut query: Query<(&mut Mass, &Particle, &Velocity)>) {
+/// Keeps the particles in the xy-plane
+fn flatten_update(mut query: Query<(&mut Transform, &mut Momentum, &mut Velocity)>) {
     query
         .par_iter_mut()
-        .for_each(|(mut mass, particle, velocity)| {
-            mass.0 = particle.mass + velocity.0.length();
+        .for_each(|(mut transform, mut momentum, mut velocity)| {
+            transform.translation.z = 0.0;
+            momentum.z = 0.0;
+            velocity.z = 0.0;
         });
 }
 
-#[derive(Component, Clone, Copy)]
-pub struct Particle {
-    /// charge in elementary charges
-    charge: f32,
-    /// mass of the particle in electronvolts
-    mass: f32,
-}
-
-impl Particle {
-    pub const ELECTRON: Self = Self {
-        charge: -1.0,
-        mass: 0.51099895,
-    };
-    pub const UP_QUARK: Self = Self {
-        charge: 2.0 / 3.0,
-        mass: (3.0 + 1.8) / 2.0, // average of its upper and lower limits
-    };
-    pub const DOWN_QUARK: Self = Self {
-        charge: -1.0 / 3.0,
-        mass: (5.8 + 4.1) / 2.0, // average of its upper and lower limits
-    };
+fn mass_update(
+    mut query: Query<(&mut Mass, &Particle, &Momentum)>,
+    dynamics: Res<Dynamics>,
+    c: Res<SpeedOfLight>,
+) {
+    query
+        .par_iter_mut()
+        .for_each(|(mut mass, particle, momentum)| {
+            *mass = Mass::new(*dynamics, *c, particle, momentum.0);
+        });
 }
 
-fn calculate_impulse<'a>(
-    particles: impl ParallelIterator<Item = &'a (&'a Particle, &'a Transform)>,
-    properties: Particle,
-    mass: Mass,
-    translation: Vec3,
-    delta_time: f32,
-) -> Vec3 {
-    let t1 = translation;
-
-    // partially calculated force using Coulomb's law
-    let semi_force = particles
-        .map(
-            |(
-                p2,
-                &Transform {
-                    translation: t2, ..
-                },
-            )| {
-                let diff = t1 - t2;
-                let dist_squared = diff.length_squared();
-
-                if dist_squared <= f32::EPSILON {
-                    return Vec3::ZERO;
-                }
-                let dir = diff / dist_squared.sqrt();
-                dir * p2.charge / (dist_squared + 1.0)
-            },
-        )
-        .sum::<Vec3>();
-
-    semi_force * (properties.charge / mass.0 * delta_time)
+#[derive(Component, Clone, Copy, Deserialize, Serialize)]
+pub struct Particle {
+    /// charge in elementary charges
+    pub charge: f32,
+    /// rest mass of the particle in eV/c²
+    pub mass: f32,
+    /// softening length in m * k_e / e, replaces the length of the
+    /// [`Softening`](crate::force::Softening) kernel when set
+    pub softening: Option<f32>,
 }
 
-#[allow(clippy::type_complexity)]
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn update(
-    mut query_mut: Query<(Entity, &Mass, &mut Velocity), (With<Particle>, With<Transform>)>,
-    query: Query<(&Particle, &Transform), With<Velocity>>,
-    time: Res<Time>,
+    mut query: Query<(
+        Entity,
+        &Particle,
+        Option<&ColorCharge>,
+        &mut Transform,
+        &mut Momentum,
+        &mut Velocity,
+    )>,
+    backend: Res<ForceBackend>,
+    law: Res<ForceLaw>,
+    strong: Res<StrongForce>,
+    field: Res<ExternalField>,
+    interaction: Res<MagneticInteraction>,
+    integrator: Res<Integrator>,
+    dynamics: Res<Dynamics>,
+    c: Res<SpeedOfLight>,
+    boundary: Res<Boundary>,
+    timestep: Res<Timestep>,
+    mut step_forces: ResMut<StepForces>,
 ) {
-    let particles = query.iter().collect::<Vec<_>>();
+    let domain = boundary.periodic_box();
+    let entities = query.iter().map(|(entity, ..)| entity).collect::<Vec<_>>();
+    let particles = query
+        .iter()
+        .map(|(_, &particle, color, ..)| (particle, color.copied()))
+        .collect::<Vec<_>>();
+    let mut translations = query
+        .iter()
+        .map(|(_, _, _, transform, ..)| transform.translation)
+        .collect::<Vec<_>>();
+    let mut momenta = query
+        .iter()
+        .map(|(.., momentum, _)| momentum.0)
+        .collect::<Vec<_>>();
+    let velocity = |i: usize, momentum: Vec3| dynamics.velocity(*c, particles[i].0.mass, momentum);
+    let (mut forces, mut levels) = if step_forces.entities == entities {
+        (step_forces.forces.take(), step_forces.levels.take())
+    } else {
+        (None, None)
+    };
 
-    query_mut
-        .par_iter_mut()
-        .for_each(|(entity, &mass, mut vel)| {
-            let (&prop, trans) = query.get(entity).unwrap();
-
-            vel.0 += calculate_impulse(
-                particles.par_iter(),
-                prop,
-                mass,
-                trans.translation,
-                time.delta_seconds(),
+    integrator.step(
+        &mut translations,
+        &mut momenta,
+        timestep.delta(),
+        velocity,
+        |translations, velocities, indices| {
+            let forces = calculate_forces(
+                *backend,
+                &law,
+                &strong,
+                &domain,
+                &particles,
+                translations,
+                indices,
             );
-        });
+            lorentz_forces(
+                &field,
+                *interaction,
+                *c,
+                &law,
+                &domain,
+                &particles,
+                translations,
+                velocities,
+                indices,
+                forces,
+            )
+        },
+        &mut forces,
+        &mut levels,
+    );
+    *step_forces = StepForces {
+        entities,
+        forces,
+        levels,
+    };
+    // the magnetic interaction depends on the velocities the forces were
+    // evaluated with, which differ from those the next step starts with
+    if *interaction != MagneticInteraction::None {
+        step_forces.clear();
+    }
+
+    for (i, ((.., mut transform, mut momentum, mut v), (translation, p))) in query
+        .iter_mut()
+        .zip(translations.into_iter().zip(momenta))
+        .enumerate()
+    {
+        transform.translation = translation;
+        momentum.0 = p;
+        v.0 = velocity(i, p);
+    }
 }
@@ -0,0 +1,875 @@
+use std::{fmt, fs, io, path::Path};
+
+use bevy::prelude::*;
+use rand::Rng;
+use ron::extensions::Extensions;
+use serde::Deserialize;
+
+use crate::{
+    annihilation::Annihilation,
+    bound::BoundStateDetection,
+    boundary::{Boundary, BoundaryCondition},
+    checkpoint::Checkpointing,
+    collision::Collisions,
+    diagnostics::{DiagnosticsSettings, DriftThreshold},
+    field::{ExternalField, MagneticInteraction},
+    force::{ForceBackend, ForceLaw, Softening},
+    integrator::Integrator,
+    output::{Output, COLUMNS},
+    particle::{Dynamics, SpeedOfLight, Timestep},
+    species::{SpeciesDefinition, SpeciesRegistry},
+    strong::{ColorCharge, StrongForce},
+    trajectory::Trajectory,
+    Seed,
+};
+
+/// Initial conditions and settings of a simulation, loaded from a RON file.
+///
+/// Settings that are left out keep the value given on the command line.
+///
+/// ```ron
+/// (
+///     seed: 42,
+///     boundary: (size: (400, 400, 400), conditions: (Periodic, Periodic, Open)),
+///     timestep: (step: 0.01, substeps: 2),
+///     integrator: Leapfrog,
+///     speed_of_light: 10,
+///     law: (softening: Spline(epsilon: 0.5)),
+///     species: [
+///         (name: "proton", charge: 1, mass: 938272088, radius: 2, color: (1, 1, 0)),
+///     ],
+///     distributions: [
+///         (species: "electron", count: 100),
+///         (species: "up_quark", count: 20, region: Sphere(center: (0, 0, 0), radius: 50)),
+///     ],
+///     particles: [
+///         (species: "proton", position: (10, 0, 0), velocity: (0, 1, 0)),
+///         (species: "down_quark", position: (0, 10, 0), color_charge: Blue),
+///     ],
+/// )
+/// ```
+#[derive(Resource, Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub boundary: Option<Boundary>,
+    #[serde(default)]
+    pub timestep: Option<Timestep>,
+    #[serde(default)]
+    pub integrator: Option<Integrator>,
+    #[serde(default)]
+    pub dynamics: Option<Dynamics>,
+    /// speed of light, the speed limit of relativistic dynamics
+    #[serde(default)]
+    pub speed_of_light: Option<f32>,
+    #[serde(default)]
+    pub backend: Option<ForceBackend>,
+    #[serde(default)]
+    pub law: Option<ForceLaw>,
+    #[serde(default)]
+    pub annihilation: Option<Annihilation>,
+    #[serde(default)]
+    pub strong: Option<StrongForce>,
+    #[serde(default)]
+    pub collisions: Option<Collisions>,
+    #[serde(default)]
+    pub field: Option<ExternalField>,
+    #[serde(default)]
+    pub magnetic_interaction: Option<MagneticInteraction>,
+    #[serde(default)]
+    pub bound_states: Option<BoundStateDetection>,
+    /// relative energy drift above which a warning is logged
+    #[serde(default)]
+    pub drift_threshold: Option<f64>,
+    #[serde(default)]
+    pub diagnostics: Option<DiagnosticsSettings>,
+    #[serde(default)]
+    pub output: Option<Output>,
+    #[serde(default)]
+    pub trajectory: Option<Trajectory>,
+    #[serde(default)]
+    pub checkpoint: Option<Checkpointing>,
+    /// species added to the standard ones in `species.ron`, replacing those
+    /// with the same name
+    #[serde(default)]
+    pub species: Vec<SpeciesDefinition>,
+    /// groups of particles spawned at random
+    #[serde(default)]
+    pub distributions: Vec<Distribution>,
+    /// individually placed particles
+    #[serde(default)]
+    pub particles: Vec<ParticleSpec>,
+}
+
+/// A number of particles of one species, spawned at random within a region
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Distribution {
+    pub species: String,
+    pub count: u32,
+    /// region the particles are spawned in, the whole box if not given
+    #[serde(default)]
+    pub region: Option<Region>,
+    #[serde(default)]
+    pub velocity: VelocityDistribution,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum Region {
+    /// Axis aligned box from `min` to `max`
+    Box {
+        min: Vec3,
+        max: Vec3,
+    },
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub enum VelocityDistribution {
+    /// All particles start at rest
+    #[default]
+    Rest,
+    /// All particles start with the same velocity
+    Fixed(Vec3),
+    /// Uniformly distributed within a ball of radius `max_speed`
+    Random { max_speed: f32 },
+}
+
+/// A single particle with an explicit position and velocity
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParticleSpec {
+    pub species: String,
+    pub position: Vec3,
+    #[serde(default)]
+    pub velocity: Vec3,
+    /// colour charge of a quark or antiquark, cycling through all colours if not given
+    #[serde(default)]
+    pub color_charge: Option<ColorCharge>,
+    /// charge in elementary charges, replacing the one of the species
+    #[serde(default)]
+    pub charge: Option<f32>,
+    /// rest mass in eV/c², replacing the one of the species
+    #[serde(default)]
+    pub mass: Option<f32>,
+    /// radius in m * k_e / e, replacing the one of the species
+    #[serde(default)]
+    pub radius: Option<f32>,
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(io::Error),
+    Parse(ron::error::SpannedError),
+    /// A value that is well-formed but not allowed, with the path to its field
+    Invalid {
+        field: String,
+        message: String,
+    },
+    /// An invalid frame given as initial conditions, with the line it is on
+    Frame {
+        line: Option<usize>,
+        message: String,
+    },
+    /// A checkpoint that could not be restored
+    Checkpoint(String),
+    /// A command line argument that is well-formed but not allowed, with the
+    /// path to the field of the scenario it sets
+    Argument {
+        field: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "could not read the scenario: {e}"),
+            ScenarioError::Parse(e) => write!(f, "{e}"),
+            ScenarioError::Invalid { field, message } => write!(f, "`{field}` {message}"),
+            ScenarioError::Frame {
+                line: Some(line),
+                message,
+            } => write!(f, "{line}: {message}"),
+            ScenarioError::Frame {
+                line: None,
+                message,
+            } => write!(f, "{message}"),
+            ScenarioError::Checkpoint(message) => write!(f, "{message}"),
+            ScenarioError::Argument { field, message } => {
+                write!(f, "invalid argument, `{field}` {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+fn invalid(field: impl Into<String>, message: &str) -> Result<(), ScenarioError> {
+    Err(ScenarioError::Invalid {
+        field: field.into(),
+        message: message.to_owned(),
+    })
+}
+
+fn positive(value: f32) -> bool {
+    value.is_finite() && value > 0.0
+}
+
+fn non_negative(value: f32) -> bool {
+    value.is_finite() && value >= 0.0
+}
+
+impl Scenario {
+    /// Particles of every species uniformly distributed over the box, at rest
+    pub fn uniform<'a>(counts: impl IntoIterator<Item = (&'a str, u32)>) -> Self {
+        Self {
+            distributions: counts
+                .into_iter()
+                .filter(|&(_, count)| count > 0)
+                .map(|(species, count)| Distribution {
+                    species: species.to_owned(),
+                    count,
+                    region: None,
+                    velocity: VelocityDistribution::Rest,
+                })
+                .collect(),
+            ..default()
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ScenarioError> {
+        Self::parse(&fs::read_to_string(path).map_err(ScenarioError::Io)?)
+    }
+
+    /// Parses and validates the RON text of a scenario
+    fn parse(text: &str) -> Result<Self, ScenarioError> {
+        let scenario = ron::Options::default()
+            .with_default_extension(Extensions::IMPLICIT_SOME)
+            .from_str::<Self>(text)
+            .map_err(ScenarioError::Parse)?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /// Fills the settings left out of the scenario with those of `defaults`
+    pub fn or(self, defaults: Scenario) -> Self {
+        Self {
+            seed: self.seed.or(defaults.seed),
+            boundary: self.boundary.or(defaults.boundary),
+            timestep: self.timestep.or(defaults.timestep),
+            integrator: self.integrator.or(defaults.integrator),
+            dynamics: self.dynamics.or(defaults.dynamics),
+            speed_of_light: self.speed_of_light.or(defaults.speed_of_light),
+            backend: self.backend.or(defaults.backend),
+            law: self.law.or(defaults.law),
+            annihilation: self.annihilation.or(defaults.annihilation),
+            strong: self.strong.or(defaults.strong),
+            collisions: self.collisions.or(defaults.collisions),
+            field: self.field.or(defaults.field),
+            magnetic_interaction: self.magnetic_interaction.or(defaults.magnetic_interaction),
+            bound_states: self.bound_states.or(defaults.bound_states),
+            drift_threshold: self.drift_threshold.or(defaults.drift_threshold),
+            diagnostics: self.diagnostics.or(defaults.diagnostics),
+            output: self.output.or(defaults.output),
+            trajectory: self.trajectory.or(defaults.trajectory),
+            checkpoint: self.checkpoint.or(defaults.checkpoint),
+            ..self
+        }
+    }
+
+    /// Inserts the settings given by the scenario as resources
+    pub fn insert_resources(&self, app: &mut App) {
+        if let Some(seed) = self.seed {
+            app.insert_resource(Seed(seed));
+        }
+        if let Some(boundary) = self.boundary {
+            app.insert_resource(boundary);
+        }
+        if let Some(timestep) = self.timestep {
+            app.insert_resource(timestep);
+        }
+        if let Some(integrator) = self.integrator {
+            app.insert_resource(integrator);
+        }
+        if let Some(dynamics) = self.dynamics {
+            app.insert_resource(dynamics);
+        }
+        if let Some(c) = self.speed_of_light {
+            app.insert_resource(SpeedOfLight(c));
+        }
+        if let Some(backend) = self.backend {
+            app.insert_resource(backend);
+        }
+        if let Some(law) = self.law {
+            app.insert_resource(law);
+        }
+        if let Some(annihilation) = self.annihilation {
+            app.insert_resource(annihilation);
+        }
+        if let Some(strong) = self.strong {
+            app.insert_resource(strong);
+        }
+        if let Some(collisions) = self.collisions {
+            app.insert_resource(collisions);
+        }
+        if let Some(field) = self.field {
+            app.insert_resource(field);
+        }
+        if let Some(interaction) = self.magnetic_interaction {
+            app.insert_resource(interaction);
+        }
+        if let Some(detection) = self.bound_states {
+            app.insert_resource(detection);
+        }
+        if let Some(threshold) = self.drift_threshold {
+            app.insert_resource(DriftThreshold(threshold));
+        }
+        if let Some(diagnostics) = self.diagnostics {
+            app.insert_resource(diagnostics);
+        }
+        if let Some(output) = self.output.clone() {
+            app.insert_resource(output);
+        }
+        if let Some(trajectory) = self.trajectory.clone() {
+            app.insert_resource(trajectory);
+        }
+        if let Some(checkpointing) = self.checkpoint.clone() {
+            app.insert_resource(checkpointing);
+        }
+    }
+
+    /// The standard species together with the ones of the scenario
+    pub fn registry(&self) -> SpeciesRegistry {
+        let mut registry = SpeciesRegistry::default();
+        for definition in &self.species {
+            registry.insert(definition.clone());
+        }
+        registry
+    }
+
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        if let Some(boundary) = &self.boundary {
+            if !boundary.size.to_array().into_iter().all(positive) {
+                return invalid("boundary.size", "must be positive along every axis");
+            }
+        }
+        if let Some(timestep) = &self.timestep {
+            if !positive(timestep.step) {
+                return invalid("timestep.step", "must be positive");
+            }
+            if timestep.substeps == 0 {
+                return invalid("timestep.substeps", "must be at least 1");
+            }
+        }
+        if let Some(Integrator::Adaptive { max_level, eta }) = self.integrator {
+            if max_level > 20 {
+                return invalid("integrator.max_level", "must be at most 20");
+            }
+            if !positive(eta) {
+                return invalid("integrator.eta", "must be positive");
+            }
+        }
+        if let Some(c) = self.speed_of_light {
+            if !positive(c) {
+                return invalid("speed_of_light", "must be positive");
+            }
+        }
+        match self.backend {
+            Some(ForceBackend::BarnesHut { theta }) if !non_negative(theta) => {
+                return invalid("backend.theta", "must not be negative");
+            }
+            Some(ForceBackend::Cutoff { radius }) if !positive(radius) => {
+                return invalid("backend.radius", "must be positive");
+            }
+            Some(ForceBackend::Ewald { alpha, .. }) if !positive(alpha) => {
+                return invalid("backend.alpha", "must be positive");
+            }
+            Some(ForceBackend::Ewald { cutoff, .. }) if !positive(cutoff) => {
+                return invalid("backend.cutoff", "must be positive");
+            }
+            _ => {}
+        }
+        // the minimum image convention only finds the neighbours within half of
+        // the box along the periodic axes
+        if let (Some(backend), Some(boundary)) = (self.backend, &self.boundary) {
+            let half = boundary
+                .conditions
+                .into_iter()
+                .zip(boundary.size.to_array())
+                .filter(|&(condition, _)| condition == BoundaryCondition::Periodic)
+                .map(|(_, size)| size)
+                .fold(f32::INFINITY, f32::min);
+            match backend {
+                ForceBackend::Cutoff { radius } if radius > half => {
+                    return invalid(
+                        "backend.radius",
+                        "must be at most half the size of the box along the periodic axes",
+                    );
+                }
+                ForceBackend::Ewald { cutoff, .. } if cutoff > half => {
+                    return invalid(
+                        "backend.cutoff",
+                        "must be at most half the size of the box along the periodic axes",
+                    );
+                }
+                _ => {}
+            }
+        }
+        if let (Some(ForceBackend::Pairwise), Some(Integrator::Adaptive { .. })) =
+            (self.backend, self.integrator)
+        {
+            return invalid(
+                "backend",
+                "can not be `Pairwise` with the adaptive integrator, which needs the forces on few particles at a time",
+            );
+        }
+        match self.law.map(|law| law.softening) {
+            Some(Softening::Plummer { epsilon } | Softening::Spline { epsilon })
+                if !positive(epsilon) =>
+            {
+                return invalid("law.softening.epsilon", "must be positive");
+            }
+            Some(Softening::HardCore { radius }) if !positive(radius) => {
+                return invalid("law.softening.radius", "must be positive");
+            }
+            _ => {}
+        }
+        if let Some(annihilation) = self.annihilation {
+            if !non_negative(annihilation.capture_distance) {
+                return invalid("annihilation.capture_distance", "must not be negative");
+            }
+        }
+
+        if let Some(strong) = self.strong {
+            if !non_negative(strong.range) {
+                return invalid("strong.range", "must not be negative");
+            }
+            if !non_negative(strong.string_tension) {
+                return invalid("strong.string_tension", "must not be negative");
+            }
+            if !strong.coupling.is_finite() {
+                return invalid("strong.coupling", "must be finite");
+            }
+        }
+
+        if let Some(collisions) = self.collisions {
+            if !(0.0..=1.0).contains(&collisions.restitution) {
+                return invalid("collisions.restitution", "must be between 0 and 1");
+            }
+        }
+
+        if let Some(field) = self.field {
+            if !field.electric.is_finite() {
+                return invalid("field.electric", "must be finite");
+            }
+            if !field.magnetic.is_finite() {
+                return invalid("field.magnetic", "must be finite");
+            }
+        }
+
+        if let Some(detection) = self.bound_states {
+            if !non_negative(detection.link_distance) {
+                return invalid("bound_states.link_distance", "must not be negative");
+            }
+        }
+
+        if let Some(threshold) = self.drift_threshold {
+            if !positive(threshold as f32) {
+                return invalid("drift_threshold", "must be positive");
+            }
+        }
+
+        if let Some(diagnostics) = &self.diagnostics {
+            if diagnostics.interval == 0 {
+                return invalid("diagnostics.interval", "must be at least 1");
+            }
+        }
+
+        if let Some(output) = &self.output {
+            if output.interval == 0 {
+                return invalid("output.interval", "must be at least 1");
+            }
+        }
+
+        if let Some(trajectory) = &self.trajectory {
+            if trajectory.interval == 0 {
+                return invalid("trajectory.interval", "must be at least 1");
+            }
+        }
+
+        if let Some(checkpoint) = &self.checkpoint {
+            if checkpoint.interval == 0 {
+                return invalid("checkpoint.interval", "must be at least 1");
+            }
+        }
+
+        for (i, species) in self.species.iter().enumerate() {
+            if self.species[..i].iter().any(|s| s.name == species.name) {
+                return invalid(format!("species[{i}].name"), "is defined more than once");
+            }
+            if COLUMNS.contains(&species.name.as_str()) {
+                return invalid(
+                    format!("species[{i}].name"),
+                    "is the name of a column of the output",
+                );
+            }
+            if !non_negative(species.mass) {
+                return invalid(format!("species[{i}].mass"), "must not be negative");
+            }
+            if !positive(species.radius) {
+                return invalid(format!("species[{i}].radius"), "must be positive");
+            }
+            if !species.charge.is_finite() {
+                return invalid(format!("species[{i}].charge"), "must be finite");
+            }
+            if matches!(species.softening, Some(softening) if !positive(softening)) {
+                return invalid(format!("species[{i}].softening"), "must be positive");
+            }
+        }
+        let registry = self.registry();
+        let unknown = |name: &str| format!("names an unknown species {name:?}");
+        for (i, species) in self.species.iter().enumerate() {
+            match &species.antiparticle {
+                Some(name) if registry.find(name).is_none() => {
+                    return invalid(format!("species[{i}].antiparticle"), &unknown(name));
+                }
+                _ => {}
+            }
+        }
+
+        // speeds are only limited once the dynamics and the speed of light are known
+        let light = match (self.dynamics, self.speed_of_light) {
+            (Some(Dynamics::Relativistic), Some(c)) => c,
+            _ => f32::INFINITY,
+        };
+        for (i, distribution) in self.distributions.iter().enumerate() {
+            let Some(species) = registry.find(&distribution.species) else {
+                return invalid(
+                    format!("distributions[{i}].species"),
+                    &unknown(&distribution.species),
+                );
+            };
+            match distribution.region {
+                Some(Region::Box { min, max }) if !min.cmple(max).all() => {
+                    return invalid(
+                        format!("distributions[{i}].region.max"),
+                        "must not be smaller than `min` along any axis",
+                    );
+                }
+                Some(Region::Sphere { radius, .. }) if !positive(radius) => {
+                    return invalid(
+                        format!("distributions[{i}].region.radius"),
+                        "must be positive",
+                    );
+                }
+                _ => {}
+            }
+            match distribution.velocity {
+                VelocityDistribution::Random { max_speed } if !non_negative(max_speed) => {
+                    return invalid(
+                        format!("distributions[{i}].velocity.max_speed"),
+                        "must not be negative",
+                    );
+                }
+                VelocityDistribution::Random { max_speed } if max_speed >= light => {
+                    return invalid(
+                        format!("distributions[{i}].velocity.max_speed"),
+                        "must be slower than light",
+                    );
+                }
+                VelocityDistribution::Fixed(velocity) if !velocity.is_finite() => {
+                    return invalid(format!("distributions[{i}].velocity"), "must be finite");
+                }
+                VelocityDistribution::Fixed(velocity)
+                    if velocity.length() >= light && registry.get(species).mass > 0.0 =>
+                {
+                    return invalid(
+                        format!("distributions[{i}].velocity"),
+                        "must be slower than light",
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        for (i, particle) in self.particles.iter().enumerate() {
+            let Some(species) = registry.find(&particle.species) else {
+                return invalid(
+                    format!("particles[{i}].species"),
+                    &unknown(&particle.species),
+                );
+            };
+            if let Some(color) = particle.color_charge {
+                if !registry.get(species).color_charge.contains(color) {
+                    return invalid(
+                        format!("particles[{i}].color_charge"),
+                        &format!("is not a colour charge of {:?}", particle.species),
+                    );
+                }
+            }
+            if !particle.position.is_finite() {
+                return invalid(format!("particles[{i}].position"), "must be finite");
+            }
+            if !particle.velocity.is_finite() {
+                return invalid(format!("particles[{i}].velocity"), "must be finite");
+            }
+            if matches!(particle.charge, Some(charge) if !charge.is_finite()) {
+                return invalid(format!("particles[{i}].charge"), "must be finite");
+            }
+            if matches!(particle.mass, Some(mass) if !non_negative(mass)) {
+                return invalid(format!("particles[{i}].mass"), "must not be negative");
+            }
+            if matches!(particle.radius, Some(radius) if !positive(radius)) {
+                return invalid(format!("particles[{i}].radius"), "must be positive");
+            }
+            let mass = particle.mass.unwrap_or(registry.get(species).mass);
+            if particle.velocity.length() >= light && mass > 0.0 {
+                return invalid(
+                    format!("particles[{i}].velocity"),
+                    "must be slower than light",
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Region {
+    /// Random point within the region, in the xy-plane if `flat`
+    pub fn sample(&self, rng: &mut impl Rng, flat: bool) -> Vec3 {
+        let mut random = |min: f32, max: f32| {
+            if min < max {
+                rng.gen_range(min..max)
+            } else {
+                min
+            }
+        };
+
+        match *self {
+            Region::Box { min, max } => Vec3::new(
+                random(min.x, max.x),
+                random(min.y, max.y),
+                if flat { 0.0 } else { random(min.z, max.z) },
+            ),
+            Region::Sphere { center, radius } => {
+                let center = if flat {
+                    center.truncate().extend(0.0)
+                } else {
+                    center
+                };
+                let z = if flat { 0.0 } else { radius };
+                loop {
+                    let offset = Vec3::new(
+                        random(-radius, radius),
+                        random(-radius, radius),
+                        random(-z, z),
+                    );
+                    if offset.length_squared() <= radius * radius {
+                        return center + offset;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl VelocityDistribution {
+    pub fn sample(&self, rng: &mut impl Rng, flat: bool) -> Vec3 {
+        match *self {
+            VelocityDistribution::Rest => Vec3::ZERO,
+            VelocityDistribution::Fixed(velocity) => velocity,
+            VelocityDistribution::Random { max_speed } => Region::Sphere {
+                center: Vec3::ZERO,
+                radius: max_speed,
+            }
+            .sample(rng, flat),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Field that the scenario given as RON is rejected for
+    fn invalid_field(text: &str) -> String {
+        match Scenario::parse(text) {
+            Err(ScenarioError::Invalid { field, .. }) => field,
+            result => panic!("expected an invalid field, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn accepts_the_documented_example() {
+        let scenario = Scenario::parse(
+            r#"(
+                seed: 42,
+                boundary: (size: (400, 400, 400), conditions: (Periodic, Periodic, Open)),
+                timestep: (step: 0.01, substeps: 2),
+                integrator: Leapfrog,
+                speed_of_light: 10,
+                law: (softening: Spline(epsilon: 0.5)),
+                species: [
+                    (name: "proton", charge: 1, mass: 938272088, radius: 2, color: (1, 1, 0)),
+                ],
+                distributions: [
+                    (species: "electron", count: 100),
+                    (species: "up_quark", count: 20, region: Sphere(center: (0, 0, 0), radius: 50)),
+                ],
+                particles: [
+                    (species: "proton", position: (10, 0, 0), velocity: (0, 1, 0)),
+                    (species: "down_quark", position: (0, 10, 0), color_charge: Blue),
+                ],
+            )"#,
+        )
+        .unwrap();
+        assert_eq!(scenario.particles.len(), 2);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        for (text, field) in [
+            (
+                "(boundary: (size: (1, 0, 1), conditions: (Open, Open, Open)))",
+                "boundary.size",
+            ),
+            (
+                "(boundary: (size: (1, inf, 1), conditions: (Open, Open, Open)))",
+                "boundary.size",
+            ),
+            ("(timestep: (step: 0, substeps: 1))", "timestep.step"),
+            ("(timestep: (step: 1, substeps: 0))", "timestep.substeps"),
+            (
+                "(integrator: Adaptive(max_level: 21, eta: 1))",
+                "integrator.max_level",
+            ),
+            ("(speed_of_light: 0)", "speed_of_light"),
+            ("(backend: BarnesHut(theta: -1))", "backend.theta"),
+            ("(backend: Cutoff(radius: 0))", "backend.radius"),
+            (
+                "(backend: Ewald(alpha: 0, cutoff: 1, k_max: 1))",
+                "backend.alpha",
+            ),
+            (
+                "(backend: Ewald(alpha: 1, cutoff: 0, k_max: 1))",
+                "backend.cutoff",
+            ),
+            (
+                "(backend: Cutoff(radius: 11), boundary: (size: (10, 20, 5), conditions: (Periodic, Periodic, Open)))",
+                "backend.radius",
+            ),
+            (
+                "(backend: Ewald(alpha: 1, cutoff: 6, k_max: 1), boundary: (size: (10, 20, 5), conditions: (Periodic, Open, Periodic)))",
+                "backend.cutoff",
+            ),
+            (
+                "(backend: Pairwise, integrator: Adaptive(max_level: 4, eta: 1))",
+                "backend",
+            ),
+            (
+                "(law: (softening: Plummer(epsilon: 0)))",
+                "law.softening.epsilon",
+            ),
+            (
+                "(law: (softening: HardCore(radius: -1)))",
+                "law.softening.radius",
+            ),
+            (
+                "(collisions: (mode: Bounce, restitution: 1.5))",
+                "collisions.restitution",
+            ),
+            ("(drift_threshold: 0)", "drift_threshold"),
+            (
+                "(diagnostics: (enabled: true, interval: 0))",
+                "diagnostics.interval",
+            ),
+        ] {
+            assert_eq!(invalid_field(text), field, "{text}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_species_and_particles() {
+        for (text, field) in [
+            (
+                r#"(species: [(name: "time", charge: 0, mass: 1, radius: 1, color: (1, 1, 1))])"#,
+                "species[0].name",
+            ),
+            (
+                r#"(species: [(name: "electron", charge: -1, mass: -1, radius: 1, color: (1, 1, 1))])"#,
+                "species[0].mass",
+            ),
+            (
+                r#"(species: [
+                    (name: "a", charge: 0, mass: 1, radius: 1, color: (1, 1, 1)),
+                    (name: "a", charge: 0, mass: 1, radius: 1, color: (1, 1, 1)),
+                ])"#,
+                "species[1].name",
+            ),
+            (
+                r#"(species: [(name: "a", charge: 0, mass: 1, radius: 1, color: (1, 1, 1), antiparticle: "b")])"#,
+                "species[0].antiparticle",
+            ),
+            (
+                r#"(distributions: [(species: "proton", count: 1)])"#,
+                "distributions[0].species",
+            ),
+            (
+                r#"(distributions: [(species: "electron", count: 1, region: Sphere(center: (0, 0, 0), radius: 0))])"#,
+                "distributions[0].region.radius",
+            ),
+            (
+                r#"(particles: [(species: "electron", position: (0, 0, 0), color_charge: Red)])"#,
+                "particles[0].color_charge",
+            ),
+            (
+                r#"(particles: [(species: "electron", position: (0, 0, 0), mass: -1)])"#,
+                "particles[0].mass",
+            ),
+            (
+                r#"(particles: [(species: "composite", position: (0, 0, 0), radius: 0)])"#,
+                "particles[0].radius",
+            ),
+        ] {
+            assert_eq!(invalid_field(text), field, "{text}");
+        }
+    }
+
+    #[test]
+    fn limits_speeds_only_with_relativistic_dynamics() {
+        let particle =
+            r#"particles: [(species: "electron", position: (0, 0, 0), velocity: (2, 0, 0))]"#;
+        assert_eq!(
+            invalid_field(&format!(
+                "(dynamics: Relativistic, speed_of_light: 1, {particle})"
+            )),
+            "particles[0].velocity"
+        );
+        assert_eq!(
+            invalid_field(
+                r#"(dynamics: Relativistic, speed_of_light: 1, distributions: [
+                    (species: "electron", count: 1, velocity: Random(max_speed: 1)),
+                ])"#
+            ),
+            "distributions[0].velocity.max_speed"
+        );
+        // massless particles always move at the speed of light
+        let photon =
+            r#"particles: [(species: "photon", position: (0, 0, 0), velocity: (2, 0, 0))]"#;
+        for text in [
+            format!("(dynamics: Relativistic, speed_of_light: 3, {particle})"),
+            format!("(dynamics: Newtonian, speed_of_light: 1, {particle})"),
+            format!("(dynamics: Relativistic, speed_of_light: 1, {photon})"),
+            // the speed of light may still be given on the command line
+            format!("({particle})"),
+        ] {
+            assert!(Scenario::parse(&text).is_ok(), "{text}");
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use bevy::prelude::*;
+use ron::extensions::Extensions;
+use serde::{Deserialize, Serialize};
+
+use crate::{particle::Particle, strong::ColorRepresentation};
+
+/// Kind of particle, defined by data instead of code
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpeciesDefinition {
+    /// unique name, used to refer to the species
+    pub name: String,
+    /// charge in elementary charges
+    pub charge: f32,
+    /// rest mass in eV/c², 0 for massless particles which move at
+    /// the speed of light and are not affected by forces
+    pub mass: f32,
+    /// radius in m * k_e / e
+    pub radius: f32,
+    /// linear rgb colour
+    pub color: [f32; 3],
+    /// softening length in m * k_e / e, see [`Particle::softening`]
+    #[serde(default)]
+    pub softening: Option<f32>,
+    /// colour charges the particles carry, see [`ColorCharge`](crate::strong::ColorCharge)
+    #[serde(default)]
+    pub color_charge: ColorRepresentation,
+    /// name of the antiparticle of the species
+    #[serde(default)]
+    pub antiparticle: Option<String>,
+}
+
+impl SpeciesDefinition {
+    pub fn particle(&self) -> Particle {
+        Particle {
+            charge: self.charge,
+            mass: self.mass,
+            softening: self.softening,
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        let [r, g, b] = self.color;
+        Color::rgb_linear(r, g, b)
+    }
+}
+
+/// Index of a species in the [`SpeciesRegistry`]
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SpeciesId(pub usize);
+
+/// All species that can be spawned
+#[derive(Resource, Clone, Debug, Deserialize, Serialize)]
+pub struct SpeciesRegistry {
+    species: Vec<SpeciesDefinition>,
+}
+
+impl Default for SpeciesRegistry {
+    /// The standard species in `species.ron`
+    fn default() -> Self {
+        let species = ron::Options::default()
+            .with_default_extension(Extensions::IMPLICIT_SOME)
+            .from_str(include_str!("../species.ron"))
+            .expect("the standard species should be valid");
+        Self { species }
+    }
+}
+
+impl SpeciesRegistry {
+    /// Adds a species, or replaces the one with the same name
+    pub fn insert(&mut self, definition: SpeciesDefinition) -> SpeciesId {
+        match self.find(&definition.name) {
+            Some(id) => {
+                self.species[id.0] = definition;
+                id
+            }
+            None => {
+                self.species.push(definition);
+                SpeciesId(self.species.len() - 1)
+            }
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<SpeciesId> {
+        self.species
+            .iter()
+            .position(|species| species.name == name)
+            .map(SpeciesId)
+    }
+
+    pub fn get(&self, id: SpeciesId) -> &SpeciesDefinition {
+        &self.species[id.0]
+    }
+
+    pub fn antiparticle(&self, id: SpeciesId) -> Option<SpeciesId> {
+        self.get(id)
+            .antiparticle
+            .as_deref()
+            .and_then(|name| self.find(name))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SpeciesId, &SpeciesDefinition)> {
+        self.species
+            .iter()
+            .enumerate()
+            .map(|(i, species)| (SpeciesId(i), species))
+    }
+}
@@ -0,0 +1,277 @@
+use std::fmt;
+
+use bevy::prelude::*;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    force::{pair_softening, ForceLaw, PeriodicBox},
+    grid::{Grid, Indexed, Located},
+    particle::Particle,
+};
+
+/// Colour charge of a quark or antiquark
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ColorCharge {
+    Red,
+    Green,
+    Blue,
+    AntiRed,
+    AntiGreen,
+    AntiBlue,
+}
+
+impl fmt::Display for ColorCharge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColorCharge::Red => "red",
+            ColorCharge::Green => "green",
+            ColorCharge::Blue => "blue",
+            ColorCharge::AntiRed => "antired",
+            ColorCharge::AntiGreen => "antigreen",
+            ColorCharge::AntiBlue => "antiblue",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Which colour charges the particles of a species carry
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ColorRepresentation {
+    /// No colour charge, not affected by the strong force
+    #[default]
+    Colorless,
+    /// Red, green or blue
+    Quark,
+    /// Antired, antigreen or antiblue
+    Antiquark,
+}
+
+impl ColorRepresentation {
+    /// The `n`th colour charge of the representation, cycling through all
+    /// three so that every three consecutive particles are colour neutral
+    pub fn nth(self, n: usize) -> Option<ColorCharge> {
+        use ColorCharge::*;
+        match self {
+            ColorRepresentation::Colorless => None,
+            ColorRepresentation::Quark => Some([Red, Green, Blue][n % 3]),
+            ColorRepresentation::Antiquark => Some([AntiRed, AntiGreen, AntiBlue][n % 3]),
+        }
+    }
+
+    pub fn contains(self, color: ColorCharge) -> bool {
+        match self {
+            ColorRepresentation::Colorless => false,
+            ColorRepresentation::Quark => !color.is_anti(),
+            ColorRepresentation::Antiquark => color.is_anti(),
+        }
+    }
+}
+
+impl ColorCharge {
+    pub const ALL: [Self; 6] = [
+        ColorCharge::Red,
+        ColorCharge::Green,
+        ColorCharge::Blue,
+        ColorCharge::AntiRed,
+        ColorCharge::AntiGreen,
+        ColorCharge::AntiBlue,
+    ];
+
+    fn is_anti(self) -> bool {
+        matches!(
+            self,
+            ColorCharge::AntiRed | ColorCharge::AntiGreen | ColorCharge::AntiBlue
+        )
+    }
+
+    fn index(self) -> usize {
+        match self {
+            ColorCharge::Red | ColorCharge::AntiRed => 0,
+            ColorCharge::Green | ColorCharge::AntiGreen => 1,
+            ColorCharge::Blue | ColorCharge::AntiBlue => 2,
+        }
+    }
+
+    /// Strength of the strong interaction between two colour charges relative to
+    /// a quark and an antiquark of the matching anticolour, negative if repulsive.
+    ///
+    /// Follows the colour factors of one gluon exchange: two quarks of different
+    /// colours attract half as strongly, while equal colours and non-matching
+    /// quark–antiquark pairs repel weakly.
+    fn factor(self, other: Self) -> f32 {
+        match (
+            self.is_anti() == other.is_anti(),
+            self.index() == other.index(),
+        ) {
+            (true, false) => 0.5,
+            (true, true) => -0.25,
+            (false, true) => 1.0,
+            (false, false) => -0.125,
+        }
+    }
+}
+
+/// Toy strong force between colour charges, derived from the Cornell potential
+/// V(r) = f * (-α/r + σr) with f the [colour factor](ColorCharge::factor).
+///
+/// The linear confinement term only acts between attracting colour charges,
+/// and the whole force vanishes beyond `range`, where the string breaks.
+#[derive(Resource, Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StrongForce {
+    /// coefficient α of the Coulomb-like term, softened like Coulomb's law
+    pub coupling: f32,
+    /// string tension σ of the linear confinement term
+    pub string_tension: f32,
+    /// distance in m * k_e / e beyond which colour charges do not interact
+    pub range: f32,
+}
+
+impl Default for StrongForce {
+    fn default() -> Self {
+        Self {
+            coupling: 2.0,
+            string_tension: 0.05,
+            range: 20.0,
+        }
+    }
+}
+
+impl StrongForce {
+    fn is_enabled(&self) -> bool {
+        self.range > 0.0 && (self.coupling != 0.0 || self.string_tension != 0.0)
+    }
+}
+
+/// Colour charge snapshotted for the strong force calculation
+struct Quark {
+    translation: Vec3,
+    color: ColorCharge,
+    softening: Option<f32>,
+}
+
+impl Located for Quark {
+    fn translation(&self) -> Vec3 {
+        self.translation
+    }
+}
+
+/// Strong forces on the `particles` at `indices`, if any of them carry colour charge
+pub fn strong_forces(
+    strong: &StrongForce,
+    law: &ForceLaw,
+    domain: &PeriodicBox,
+    particles: &[(Particle, Option<ColorCharge>)],
+    translations: &[Vec3],
+    indices: &[usize],
+) -> Option<Vec<Vec3>> {
+    if !strong.is_enabled() || particles.iter().all(|(.., color)| color.is_none()) {
+        return None;
+    }
+    let grid = Grid::new(
+        particles
+            .iter()
+            .zip(translations)
+            .filter_map(|((particle, color), &translation)| {
+                Some(Quark {
+                    translation,
+                    color: (*color)?,
+                    softening: particle.softening,
+                })
+            }),
+        strong.range,
+        *domain,
+    );
+
+    let forces = indices
+        .par_iter()
+        .map(|&i| {
+            let (particle, Some(color)) = particles[i] else {
+                return Vec3::ZERO;
+            };
+            grid.sum_neighbours(translations[i], strong.range, |diff, quark| {
+                let dist = diff.length();
+                if dist <= f32::EPSILON {
+                    return Vec3::ZERO;
+                }
+                let factor = color.factor(quark.color);
+                let coulomb = law.semi_force(
+                    diff,
+                    strong.coupling,
+                    pair_softening(particle.softening, quark.softening),
+                );
+                let confinement = if factor > 0.0 {
+                    diff * (strong.string_tension / dist)
+                } else {
+                    Vec3::ZERO
+                };
+                -(coulomb + confinement) * factor
+            })
+        })
+        .collect();
+    Some(forces)
+}
+
+/// Potential energy of the strong interaction between the `particles` placed at
+/// `translations`, shifted to vanish at the range so it is continuous
+pub fn strong_potential_energy(
+    strong: &StrongForce,
+    law: &ForceLaw,
+    domain: &PeriodicBox,
+    particles: &[(Particle, Option<ColorCharge>)],
+    translations: &[Vec3],
+) -> f64 {
+    if !strong.is_enabled() {
+        return 0.0;
+    }
+    let quarks = particles
+        .iter()
+        .zip(translations)
+        .filter_map(|((particle, color), &translation)| {
+            Some(Quark {
+                translation,
+                color: (*color)?,
+                softening: particle.softening,
+            })
+        })
+        .collect::<Vec<_>>();
+    if quarks.is_empty() {
+        return 0.0;
+    }
+    let grid = Grid::new(
+        quarks.iter().enumerate().map(|(index, quark)| Indexed {
+            index,
+            translation: quark.translation,
+        }),
+        strong.range,
+        *domain,
+    );
+
+    let rows = quarks
+        .par_iter()
+        .enumerate()
+        .map(|(i, quark)| {
+            let mut energy = 0.0;
+            grid.for_each_neighbour(quark.translation, strong.range, |diff, other| {
+                let j = other.index;
+                if j <= i {
+                    return;
+                }
+                let other = &quarks[j];
+                let factor = quark.color.factor(other.color);
+                let softening = pair_softening(quark.softening, other.softening);
+                let coulomb = law.potential(diff.length(), strong.coupling, softening)
+                    - law.potential(strong.range, strong.coupling, softening);
+                let confinement = if factor > 0.0 {
+                    strong.string_tension * (diff.length() - strong.range)
+                } else {
+                    0.0
+                };
+                energy += ((confinement - coulomb) * factor) as f64;
+            });
+            energy
+        })
+        .collect::<Vec<_>>();
+    rows.into_iter().sum()
+}
@@ -0,0 +1,539 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    boundary::{Boundary, BoundaryCondition},
+    checkpoint::Restarted,
+    particle::{clock_update, Particle, PhysicsStep, Radius, SimulationClock, Velocity},
+    scenario::{ParticleSpec, ScenarioError},
+    species::{SpeciesId, SpeciesRegistry},
+    strong::ColorCharge,
+};
+
+/// Settings of the trajectory, written as extended XYZ frames that tools like
+/// OVITO and VMD can read
+#[derive(Resource, Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Trajectory {
+    /// file the frames are appended to, nothing is written if not set
+    pub path: Option<PathBuf>,
+    /// number of integration steps between frames
+    pub interval: u64,
+}
+
+impl Default for Trajectory {
+    fn default() -> Self {
+        Self {
+            path: None,
+            interval: 100,
+        }
+    }
+}
+
+/// Writes a frame of the initial conditions and every `interval` steps after.
+///
+/// A simulation restarted from a checkpoint continues the existing file, without
+/// the frames the original run wrote after the checkpoint.
+pub struct TrajectoryPlugin;
+
+impl Plugin for TrajectoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Trajectory>()
+            .add_systems(Startup, trajectory_setup)
+            .add_systems(
+                PostStartup,
+                trajectory_update.run_if(not(resource_exists::<Restarted>)),
+            )
+            .add_systems(PhysicsStep, trajectory_update.after(clock_update));
+    }
+}
+
+/// Open trajectory file, removed when writing fails
+#[derive(Resource)]
+struct TrajectoryWriter(BufWriter<File>);
+
+const PROPERTIES: &str = "species:S:1:pos:R:3:velo:R:3:charge:R:1:mass:R:1:radius:R:1:color:S:1";
+
+/// Value of the colour column for particles without colour charge
+const COLORLESS: &str = "none";
+
+fn trajectory_setup(
+    mut commands: Commands,
+    trajectory: Res<Trajectory>,
+    restarted: Option<Res<Restarted>>,
+) {
+    let Some(path) = &trajectory.path else {
+        return;
+    };
+    let file = match restarted {
+        Some(restarted) if path.exists() => resume(path, restarted.0),
+        _ => File::create(path),
+    };
+    match file {
+        Ok(file) => commands.insert_resource(TrajectoryWriter(BufWriter::new(file))),
+        Err(error) => error!("could not open {}: {error}", path.display()),
+    }
+}
+
+/// Opens the trajectory written up to the checkpoint at `step`, dropping the
+/// frames after it
+fn resume(path: &Path, step: u64) -> io::Result<File> {
+    let text = fs::read_to_string(path)?;
+    let lines = text.split_inclusive('\n').collect::<Vec<_>>();
+
+    let mut start = 0;
+    let mut len = 0;
+    while let Some(count) = lines
+        .get(start)
+        .and_then(|line| line.trim().parse::<usize>().ok())
+    {
+        let frame_step = lines.get(start + 1).and_then(|comment| {
+            key_values(comment)
+                .into_iter()
+                .find(|(key, _)| key == "step")
+                .and_then(|(_, value)| value.parse::<u64>().ok())
+        });
+        let Some(end) = count.checked_add(start + 2) else {
+            break;
+        };
+        if end > lines.len() || frame_step.is_none_or(|frame_step| frame_step > step) {
+            break;
+        }
+        len += lines[start..end]
+            .iter()
+            .map(|line| line.len())
+            .sum::<usize>();
+        start = end;
+    }
+
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(len as u64)?;
+    file.seek(SeekFrom::End(0))?;
+    Ok(file)
+}
+
+#[allow(clippy::type_complexity)]
+fn trajectory_update(
+    mut commands: Commands,
+    query: Query<(
+        &SpeciesId,
+        &Particle,
+        &Radius,
+        &Transform,
+        &Velocity,
+        Option<&ColorCharge>,
+    )>,
+    writer: Option<ResMut<TrajectoryWriter>>,
+    trajectory: Res<Trajectory>,
+    clock: Res<SimulationClock>,
+    registry: Res<SpeciesRegistry>,
+    boundary: Res<Boundary>,
+) {
+    let Some(mut writer) = writer else {
+        return;
+    };
+    if !clock.steps.is_multiple_of(trajectory.interval.max(1)) {
+        return;
+    }
+
+    let file = &mut writer.0;
+    let size = boundary.size * 2.0;
+    let min = -boundary.size;
+    let pbc = boundary
+        .conditions
+        .map(|c| {
+            if c == BoundaryCondition::Periodic {
+                "T"
+            } else {
+                "F"
+            }
+        })
+        .join(" ");
+    let result = (|| {
+        writeln!(file, "{}", query.iter().len())?;
+        writeln!(
+            file,
+            "Lattice=\"{} 0 0 0 {} 0 0 0 {}\" Origin=\"{} {} {}\" Properties={PROPERTIES} \
+             Time={} Step={} pbc=\"{pbc}\"",
+            size.x, size.y, size.z, min.x, min.y, min.z, clock.elapsed, clock.steps
+        )?;
+        for (species, particle, radius, transform, velocity, color) in query.iter() {
+            let (x, v) = (transform.translation, velocity.0);
+            writeln!(
+                file,
+                "{} {} {} {} {} {} {} {} {} {} {}",
+                registry.get(*species).name,
+                x.x,
+                x.y,
+                x.z,
+                v.x,
+                v.y,
+                v.z,
+                particle.charge,
+                particle.mass,
+                radius.0,
+                color.map_or(COLORLESS.to_owned(), ToString::to_string)
+            )?;
+        }
+        file.flush()
+    })();
+
+    if let Err(error) = result {
+        error!("could not write the trajectory, stopping it: {error}");
+        commands.remove_resource::<TrajectoryWriter>();
+    }
+}
+
+fn frame_error(line: usize, message: impl Into<String>) -> ScenarioError {
+    ScenarioError::Frame {
+        line: Some(line),
+        message: message.into(),
+    }
+}
+
+/// Splits the comment line of an extended XYZ frame into its `key=value`
+/// pairs, where values may be quoted
+fn key_values(comment: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut chars = comment.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let key = std::iter::from_fn(|| chars.next_if(|&c| c != '=' && !c.is_whitespace()))
+            .collect::<String>();
+        if key.is_empty() {
+            break;
+        }
+        let value = if chars.next_if_eq(&'=').is_none() {
+            String::new()
+        } else if chars.next_if_eq(&'"').is_some() {
+            let value = std::iter::from_fn(|| chars.next_if(|&c| c != '"')).collect();
+            chars.next();
+            value
+        } else {
+            std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace())).collect()
+        };
+        pairs.push((key.to_lowercase(), value));
+    }
+    pairs
+}
+
+/// Reads the frame at `index` of an extended XYZ file, or the last one if not
+/// given, as particles to use as initial conditions.
+///
+/// The species, positions and velocities are used, and the charges, masses,
+/// radii and colour charges when given, which otherwise follow from the species. Without velocities
+/// the particles start at rest.
+pub fn read_frame(path: &Path, index: Option<usize>) -> Result<Vec<ParticleSpec>, ScenarioError> {
+    let text = fs::read_to_string(path).map_err(|error| ScenarioError::Frame {
+        line: None,
+        message: format!("could not read the frame: {error}"),
+    })?;
+    let lines = text.lines().collect::<Vec<_>>();
+
+    // line numbers of the frame headers and their number of particles
+    let mut frames = Vec::new();
+    let mut start = 0;
+    while start < lines.len() && !lines[start].trim().is_empty() {
+        let count = lines[start]
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| frame_error(start + 1, "expected the number of particles"))?;
+        frames.push((start, count));
+        start = match count.checked_add(start + 2) {
+            Some(end) if end <= lines.len() => end,
+            _ => return Err(frame_error(lines.len(), "the last frame is incomplete")),
+        };
+    }
+    let (start, count) = match index {
+        Some(index) => *frames.get(index).ok_or_else(|| ScenarioError::Frame {
+            line: None,
+            message: format!("there is no frame {index}, only {}", frames.len()),
+        })?,
+        None => *frames.last().ok_or_else(|| ScenarioError::Frame {
+            line: None,
+            message: "there are no frames".to_owned(),
+        })?,
+    };
+
+    // first columns of the properties that are used
+    let properties = key_values(lines[start + 1])
+        .into_iter()
+        .find(|(key, _)| key == "properties")
+        .map_or("species:S:1:pos:R:3".to_owned(), |(_, value)| value);
+    let fields = properties.split(':').collect::<Vec<_>>();
+    if fields.len() % 3 != 0 {
+        return Err(frame_error(start + 2, "malformed Properties"));
+    }
+    let (mut species, mut position, mut velocity) = (None, None, None);
+    let (mut charge, mut mass, mut radius, mut color) = (None, None, None, None);
+    let mut column = 0;
+    for property in fields.chunks(3) {
+        let count = property[2]
+            .parse::<usize>()
+            .map_err(|_| frame_error(start + 2, "malformed Properties"))?;
+        match (property[0], property[1], count) {
+            ("species", "S", 1) => species = Some(column),
+            ("pos", "R", 3) => position = Some(column),
+            ("velo", "R", 3) => velocity = Some(column),
+            ("charge", "R", 1) => charge = Some(column),
+            ("mass", "R", 1) => mass = Some(column),
+            ("radius", "R", 1) => radius = Some(column),
+            ("color", "S", 1) => color = Some(column),
+            _ => {}
+        }
+        column += count;
+    }
+    let (Some(species), Some(position)) = (species, position) else {
+        return Err(frame_error(
+            start + 2,
+            "Properties needs species:S:1 and pos:R:3",
+        ));
+    };
+
+    lines[start + 2..]
+        .iter()
+        .take(count)
+        .enumerate()
+        .map(|(i, line)| {
+            let line_number = start + 3 + i;
+            let values = line.split_whitespace().collect::<Vec<_>>();
+            if values.len() < column {
+                return Err(frame_error(
+                    line_number,
+                    format!("expected {column} values, got {}", values.len()),
+                ));
+            }
+            let scalar = |column: usize| -> Result<f32, ScenarioError> {
+                values[column]
+                    .parse()
+                    .map_err(|_| frame_error(line_number, "expected a number"))
+            };
+            let vector = |first: usize| -> Result<Vec3, ScenarioError> {
+                let mut vector = Vec3::ZERO;
+                for axis in 0..3 {
+                    vector[axis] = scalar(first + axis)?;
+                }
+                Ok(vector)
+            };
+            let color_charge = |column: usize| -> Result<Option<ColorCharge>, ScenarioError> {
+                match values[column] {
+                    COLORLESS => Ok(None),
+                    name => ColorCharge::ALL
+                        .into_iter()
+                        .find(|color| color.to_string() == name)
+                        .map(Some)
+                        .ok_or_else(|| frame_error(line_number, "expected a colour charge")),
+                }
+            };
+            Ok(ParticleSpec {
+                species: values[species].to_owned(),
+                position: vector(position)?,
+                velocity: velocity.map(vector).transpose()?.unwrap_or_default(),
+                color_charge: color.map(color_charge).transpose()?.flatten(),
+                charge: charge.map(scalar).transpose()?,
+                mass: mass.map(scalar).transpose()?,
+                radius: radius.map(scalar).transpose()?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    /// Writes `text` to a file of its own in the temporary directory
+    fn temporary(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("fysiks-{}-{name}.xyz", std::process::id()));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    /// Line and message of the error reading the frame at `index` of `text`
+    fn read_error(text: &str, index: Option<usize>) -> (Option<usize>, String) {
+        let path = temporary("error", text);
+        let result = read_frame(&path, index);
+        fs::remove_file(path).unwrap();
+        match result {
+            Err(ScenarioError::Frame { line, message }) => (line, message),
+            result => panic!("expected a frame error, got {result:?}"),
+        }
+    }
+
+    const FRAME: &str = "2\nProperties=species:S:1:pos:R:3\nelectron 1 2 3\npositron 4 5 6\n";
+
+    #[test]
+    fn reads_frames_without_optional_columns() {
+        let path = temporary("plain", &format!("{FRAME}1\nStep=1\nup_quark 7 8 9\n"));
+        let first = read_frame(&path, Some(0)).unwrap();
+        let last = read_frame(&path, None).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(first.len(), 2);
+        assert_eq!(first[1].species, "positron");
+        assert_eq!(first[1].position, Vec3::new(4.0, 5.0, 6.0));
+        assert_eq!(first[1].velocity, Vec3::ZERO);
+        assert_eq!(first[1].charge, None);
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].species, "up_quark");
+    }
+
+    #[test]
+    fn reports_the_line_of_errors() {
+        for (text, index, line, message) in [
+            ("", None, None, "there are no frames"),
+            ("two\n", None, Some(1), "expected the number of particles"),
+            (
+                "3\nStep=0\nelectron 0 0 0\n",
+                None,
+                Some(3),
+                "the last frame is incomplete",
+            ),
+            (
+                "18446744073709551615\nStep=0\n",
+                None,
+                Some(2),
+                "the last frame is incomplete",
+            ),
+            (FRAME, Some(1), None, "there is no frame 1, only 1"),
+            (
+                "1\nProperties=species:S:1:pos:R\nelectron 0 0 0\n",
+                None,
+                Some(2),
+                "malformed Properties",
+            ),
+            (
+                "1\nProperties=species:S:1:velo:R:3\nelectron 0 0 0\n",
+                None,
+                Some(2),
+                "Properties needs species:S:1 and pos:R:3",
+            ),
+            (
+                "1\nStep=0\nelectron 0 0\n",
+                None,
+                Some(3),
+                "expected 4 values, got 3",
+            ),
+            (
+                "1\nStep=0\nelectron 0 zero 0\n",
+                None,
+                Some(3),
+                "expected a number",
+            ),
+            (
+                "1\nProperties=species:S:1:pos:R:3:mass:R:1\nelectron 0 0 0 heavy\n",
+                None,
+                Some(3),
+                "expected a number",
+            ),
+            (
+                "1\nProperties=species:S:1:pos:R:3:color:S:1\nup_quark 0 0 0 purple\n",
+                None,
+                Some(3),
+                "expected a colour charge",
+            ),
+        ] {
+            assert_eq!(
+                read_error(text, index),
+                (line, message.to_owned()),
+                "{text:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn missing_files_are_errors() {
+        let path = std::env::temp_dir().join("fysiks-missing.xyz");
+        assert!(matches!(
+            read_frame(&path, None),
+            Err(ScenarioError::Frame { line: None, .. })
+        ));
+    }
+
+    #[test]
+    fn written_frames_read_back() {
+        let path = temporary("round-trip", "");
+        let registry = SpeciesRegistry::default();
+        let composite = registry.find("composite").unwrap();
+        let electron = registry.find("electron").unwrap();
+        let quark = registry.find("up_quark").unwrap();
+
+        let mut world = World::new();
+        world.insert_resource(TrajectoryWriter(BufWriter::new(
+            File::create(&path).unwrap(),
+        )));
+        world.insert_resource(Trajectory::default());
+        world.insert_resource(SimulationClock::default());
+        world.insert_resource(Boundary::default());
+        let particles = [
+            (
+                electron,
+                registry.get(electron).particle(),
+                registry.get(electron).radius,
+                Vec3::new(-1.5, 2.25, 0.0),
+                Vec3::new(0.125, -0.5, 0.0),
+                None,
+            ),
+            (
+                composite,
+                Particle {
+                    charge: 0.33333334,
+                    mass: 7.35,
+                    softening: None,
+                },
+                0.70710677,
+                Vec3::new(100.0, -37.5, 12.0),
+                Vec3::new(0.0, 0.0, -0.25),
+                None,
+            ),
+            // last, as the query visits it after the colourless particles
+            (
+                quark,
+                registry.get(quark).particle(),
+                registry.get(quark).radius,
+                Vec3::new(3.0, 0.5, -8.0),
+                Vec3::ZERO,
+                Some(ColorCharge::Green),
+            ),
+        ];
+        world.insert_resource(registry.clone());
+        for (species, particle, radius, translation, velocity, color) in particles {
+            let mut entity = world.spawn((
+                species,
+                particle,
+                Radius(radius),
+                Transform::from_translation(translation),
+                Velocity(velocity),
+            ));
+            if let Some(color) = color {
+                entity.insert(color);
+            }
+        }
+        world.run_system_once(trajectory_update);
+        world.remove_resource::<TrajectoryWriter>();
+
+        let frame = read_frame(&path, None).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(frame.len(), particles.len());
+        for (spec, (species, particle, radius, translation, velocity, color)) in
+            frame.iter().zip(particles)
+        {
+            assert_eq!(spec.species, registry.get(species).name);
+            assert_eq!(spec.position, translation);
+            assert_eq!(spec.velocity, velocity);
+            assert_eq!(spec.charge, Some(particle.charge));
+            assert_eq!(spec.mass, Some(particle.mass));
+            assert_eq!(spec.radius, Some(radius));
+            assert_eq!(spec.color_charge, color);
+        }
+    }
+}
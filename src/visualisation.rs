@@ -0,0 +1,187 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{
+    input::mouse::{MouseMotion, MouseWheel},
+    prelude::*,
+    render::mesh::{CircleMeshBuilder, SphereKind, SphereMeshBuilder},
+    sprite::Mesh2dHandle,
+};
+
+use crate::{
+    boundary::Boundary,
+    particle::{Dimensions, Radius},
+    species::{SpeciesId, SpeciesRegistry},
+};
+
+/// Radians the camera orbits per pixel of mouse movement
+const ORBIT_SPEED: f32 = 0.005;
+/// Fraction of the distance to the focus the camera pans per pixel of mouse movement
+const PAN_SPEED: f32 = 0.001;
+/// Relative change of the distance to the focus per line scrolled
+const ZOOM_SPEED: f32 = 0.1;
+
+/// Renders the particles, in 2D or 3D depending on [`Dimensions`]
+pub struct VisualisationPlugin;
+
+impl Plugin for VisualisationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup).add_systems(
+            Update,
+            (
+                visualise,
+                radius_update,
+                orbit_camera_update.run_if(resource_equals(Dimensions::Three)),
+            ),
+        );
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+    mut standard_materials: ResMut<Assets<StandardMaterial>>,
+    dimensions: Res<Dimensions>,
+    boundary: Res<Boundary>,
+    registry: Res<SpeciesRegistry>,
+) {
+    let mesh = meshes.add(CircleMeshBuilder::new(1.0, 5).build());
+    let sphere_mesh =
+        meshes.add(SphereMeshBuilder::new(1.0, SphereKind::Ico { subdivisions: 1 }).build());
+    let visualisations = registry
+        .iter()
+        .map(|(_, species)| Visualisation {
+            mesh: mesh.clone(),
+            material: color_materials.add(species.color()),
+            sphere_mesh: sphere_mesh.clone(),
+            standard_material: standard_materials.add(species.color()),
+        })
+        .collect();
+    commands.insert_resource(Visualisations(visualisations));
+
+    match *dimensions {
+        Dimensions::Two => {
+            commands.spawn(Camera2dBundle::default());
+        }
+        Dimensions::Three => {
+            let orbit = OrbitCamera {
+                focus: Vec3::ZERO,
+                distance: boundary.size.length() * 2.0,
+                yaw: 0.0,
+                pitch: 0.0,
+            };
+            commands.spawn((
+                Camera3dBundle {
+                    transform: orbit.transform(),
+                    projection: PerspectiveProjection {
+                        far: orbit.distance * 10.0,
+                        ..default()
+                    }
+                    .into(),
+                    ..default()
+                },
+                orbit,
+            ));
+            commands.spawn(DirectionalLightBundle {
+                transform: Transform::from_xyz(1.0, 2.0, 3.0).looking_at(Vec3::ZERO, Vec3::Y),
+                ..default()
+            });
+            commands.insert_resource(AmbientLight {
+                color: Color::WHITE,
+                brightness: 500.0,
+            });
+        }
+    }
+}
+
+/// Meshes and materials of a species, with unit radius to be scaled by the [`Radius`]
+struct Visualisation {
+    mesh: Handle<Mesh>,
+    material: Handle<ColorMaterial>,
+    sphere_mesh: Handle<Mesh>,
+    standard_material: Handle<StandardMaterial>,
+}
+
+/// [`Visualisation`] of every species, indexed by [`SpeciesId`]
+#[derive(Resource)]
+struct Visualisations(Vec<Visualisation>);
+
+/// Adds the mesh and material matching [`Dimensions`] to newly spawned particles,
+/// and replaces them when the species changes
+fn visualise(
+    mut commands: Commands,
+    query: Query<(Entity, &SpeciesId), Changed<SpeciesId>>,
+    visualisations: Res<Visualisations>,
+    dimensions: Res<Dimensions>,
+) {
+    for (entity, species) in query.iter() {
+        let visualisation = &visualisations.0[species.0];
+        let mut entity = commands.entity(entity);
+        match *dimensions {
+            Dimensions::Two => entity.insert((
+                Mesh2dHandle(visualisation.mesh.clone()),
+                visualisation.material.clone(),
+            )),
+            Dimensions::Three => entity.insert((
+                visualisation.sphere_mesh.clone(),
+                visualisation.standard_material.clone(),
+            )),
+        };
+    }
+}
+
+/// Scales the meshes of the particles to their radius
+fn radius_update(mut query: Query<(&Radius, &mut Transform), Changed<Radius>>) {
+    for (radius, mut transform) in query.iter_mut() {
+        transform.scale = Vec3::splat(radius.0);
+    }
+}
+
+/// Camera that orbits around a focus point, controlled with the mouse.
+///
+/// Dragging with the left button orbits, with the right button pans and
+/// scrolling zooms.
+#[derive(Component)]
+pub struct OrbitCamera {
+    focus: Vec3,
+    distance: f32,
+    yaw: f32,
+    pitch: f32,
+}
+
+impl OrbitCamera {
+    fn transform(&self) -> Transform {
+        let rotation = Quat::from_euler(EulerRot::YXZ, self.yaw, self.pitch, 0.0);
+        Transform {
+            translation: self.focus + rotation * Vec3::Z * self.distance,
+            rotation,
+            ..default()
+        }
+    }
+}
+
+fn orbit_camera_update(
+    mut query: Query<(&mut OrbitCamera, &mut Transform)>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    mut motion: EventReader<MouseMotion>,
+    mut wheel: EventReader<MouseWheel>,
+) {
+    let delta = motion.read().map(|event| event.delta).sum::<Vec2>();
+    let scroll = wheel.read().map(|event| event.y).sum::<f32>();
+
+    for (mut orbit, mut transform) in query.iter_mut() {
+        if buttons.pressed(MouseButton::Left) {
+            orbit.yaw -= delta.x * ORBIT_SPEED;
+            orbit.pitch = (orbit.pitch - delta.y * ORBIT_SPEED).clamp(-FRAC_PI_2, FRAC_PI_2);
+        }
+        if buttons.pressed(MouseButton::Right) {
+            let right = transform.rotation * Vec3::X;
+            let up = transform.rotation * Vec3::Y;
+            let distance = orbit.distance;
+            orbit.focus += (up * delta.y - right * delta.x) * distance * PAN_SPEED;
+        }
+        orbit.distance *= (-scroll * ZOOM_SPEED).exp();
+
+        *transform = orbit.transform();
+    }
+}